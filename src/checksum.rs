@@ -0,0 +1,55 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+// CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320) as used by zlib/gzip
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+const CRC32_TABLE: [u32; 256] = build_crc32_table();
+
+const fn build_crc32_table() -> [u32; 256] {
+	let mut table = [0u32; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = i as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 1 == 1 {
+				(crc >> 1) ^ CRC32_POLYNOMIAL
+			} else {
+				crc >> 1
+			};
+			bit += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+	crc32_update(0, data)
+}
+
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+	let mut crc = !crc;
+	for byte in data {
+		crc = CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+	}
+	!crc
+}
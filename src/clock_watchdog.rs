@@ -0,0 +1,206 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use logfile_map::LogfileMap;
+use serde_json as json;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use storage_backend::StorageBackend;
+use time::Clock;
+
+const CLOCK_WATCHDOG_FILE_NAME: &str = "clock_watchdog";
+
+// the last observed time is written to disk at most once per interval
+const CLOCK_WATCHDOG_PERSIST_INTERVAL_MICROS: u64 = 60 * 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockWatchdogMode {
+	// fail all further operations once the watchdog was triggered
+	Panic,
+	// delete all stored measurements once the watchdog was triggered
+	Wipe,
+}
+
+// Jump thresholds are in microseconds. A threshold of None disables the check
+// for the respective direction.
+#[derive(Debug, Clone)]
+pub struct ClockWatchdogConfig {
+	pub mode: ClockWatchdogMode,
+	pub max_jump_forward: Option<u64>,
+	pub max_jump_backward: Option<u64>,
+}
+
+// The clock watchdog remembers the last observed wall clock time in the data
+// directory and compares it to the current wall clock time on every check. If
+// the clock jumped by more than the configured thresholds, the watchdog is
+// triggered.
+#[derive(Debug, Clone)]
+pub struct ClockWatchdog {
+	config: ClockWatchdogConfig,
+	clock: Arc<dyn Clock>,
+	backend: Arc<dyn StorageBackend>,
+	path: PathBuf,
+	state: Arc<Mutex<ClockWatchdogState>>,
+}
+
+#[derive(Debug)]
+struct ClockWatchdogState {
+	time_observed: Option<u64>,
+	time_persisted: Option<u64>,
+	triggered: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ClockWatchdogFile {
+	time_observed: u64,
+}
+
+impl ClockWatchdog {
+	pub fn open(
+		backend: Arc<dyn StorageBackend>,
+		datadir: &Path,
+		config: ClockWatchdogConfig,
+		clock: Arc<dyn Clock>,
+	) -> Result<ClockWatchdog, ::Error> {
+		let path = datadir.join(CLOCK_WATCHDOG_FILE_NAME);
+
+		let time_observed = if let Some(data) = backend.read(&path)? {
+			match json::from_slice::<ClockWatchdogFile>(&data) {
+				Ok(v) => Some(v.time_observed),
+				Err(e) => {
+					return Err(err_server!(
+						"error while decoding clock watchdog file: {}",
+						e
+					))
+				}
+			}
+		} else {
+			None
+		};
+
+		info!(
+			"Starting clock watchdog; mode={:?} last_observed_time={:?}",
+			config.mode, time_observed
+		);
+
+		let state = ClockWatchdogState {
+			time_observed,
+			time_persisted: time_observed,
+			triggered: false,
+		};
+
+		Ok(ClockWatchdog {
+			config,
+			clock,
+			backend,
+			path,
+			state: Arc::new(Mutex::new(state)),
+		})
+	}
+
+	pub fn check(&self, logfile_map: &LogfileMap) -> Result<(), ::Error> {
+		let now = self.clock.get_unix_microseconds()?;
+
+		let mut state = match self.state.lock() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if state.triggered {
+			return Err(err_clock!(
+				"clock watchdog was triggered; refusing to operate on potentially incorrect data"
+			));
+		}
+
+		let time_observed = match state.time_observed {
+			Some(v) => v,
+			None => now,
+		};
+
+		let jumped_forward = match self.config.max_jump_forward {
+			Some(max_jump) => now > time_observed && now - time_observed > max_jump,
+			None => false,
+		};
+
+		let jumped_backward = match self.config.max_jump_backward {
+			Some(max_jump) => now < time_observed && time_observed - now > max_jump,
+			None => false,
+		};
+
+		if jumped_forward || jumped_backward {
+			error!(
+				"Clock watchdog triggered; last observed time: {}, current time: {}",
+				time_observed, now
+			);
+
+			match self.config.mode {
+				ClockWatchdogMode::Panic => {
+					state.triggered = true;
+					return Err(err_clock!(
+						"clock watchdog triggered: the system clock jumped from {} to {}",
+						time_observed,
+						now
+					));
+				}
+				ClockWatchdogMode::Wipe => {
+					warn!("Clock watchdog is in wipe mode, deleting all measurements...");
+					logfile_map.clear()?;
+					state.time_observed = Some(now);
+					state.time_persisted = None;
+				}
+			}
+		}
+
+		// never move the observed time backwards so that a series of small
+		// backwards jumps can not add up to a large one
+		if now > time_observed || state.time_observed.is_none() {
+			state.time_observed = Some(now);
+		}
+
+		let persist = match (state.time_observed, state.time_persisted) {
+			(Some(observed), Some(persisted)) => {
+				observed >= persisted + CLOCK_WATCHDOG_PERSIST_INTERVAL_MICROS
+			}
+			_ => true,
+		};
+
+		if persist {
+			let time_observed = state.time_observed.unwrap_or(now);
+			self.persist(time_observed)?;
+			state.time_persisted = Some(time_observed);
+		}
+
+		Ok(())
+	}
+
+	fn persist(&self, time_observed: u64) -> Result<(), ::Error> {
+		let encoded = match json::to_vec(&ClockWatchdogFile { time_observed }) {
+			Ok(v) => v,
+			Err(e) => {
+				return Err(err_server!(
+					"error while encoding clock watchdog file: {}",
+					e
+				))
+			}
+		};
+
+		::fs_util::write_file_atomic(&*self.backend, &self.path, &encoded, true)
+	}
+}
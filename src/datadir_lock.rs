@@ -0,0 +1,90 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use libc;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE_NAME: &str = "lock";
+
+// An exclusive advisory lock (flock) on the data directory that prevents two
+// writers from using the same data directory at the same time. The lock file
+// contains the PID of the process holding the lock. The lock is released when
+// the lock file is closed, i.e. when the DatadirLock is dropped or the process
+// exits.
+#[derive(Debug)]
+pub struct DatadirLock {
+	path: PathBuf,
+	file: fs::File,
+}
+
+impl DatadirLock {
+	pub fn acquire(datadir: &Path) -> Result<DatadirLock, ::Error> {
+		let path = datadir.join(LOCK_FILE_NAME);
+
+		let mut file = fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(&path)?;
+
+		if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+			let error = io::Error::last_os_error();
+			if error.kind() != io::ErrorKind::WouldBlock {
+				return Err(error.into());
+			}
+
+			let mut pid = String::new();
+			file.read_to_string(&mut pid)?;
+
+			return Err(err_server!(
+				"data directory {:?} is locked by another process (pid {})",
+				datadir,
+				match pid.trim() {
+					"" => "unknown",
+					pid => pid,
+				}
+			));
+		}
+
+		// record our pid in the lock file
+		file.set_len(0)?;
+		file.seek(SeekFrom::Start(0))?;
+		file.write_all(format!("{}\n", ::std::process::id()).as_bytes())?;
+		file.sync_data()?;
+
+		debug!("Acquired data directory lock; path={:?}", path);
+		Ok(DatadirLock { path, file })
+	}
+}
+
+impl Drop for DatadirLock {
+	fn drop(&mut self) {
+		debug!("Releasing data directory lock; path={:?}", self.path);
+
+		// clear the pid before the lock is released by closing the file
+		if let Err(e) = self.file.set_len(0) {
+			warn!("Error while clearing lock file {:?}: {:?}", self.path, e);
+		}
+	}
+}
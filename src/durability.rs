@@ -0,0 +1,110 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use logfile_map::LogfileMap;
+use periodic_task::PeriodicTask;
+use std::time::Duration;
+
+// When appended measurements and transactions are synced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+	// sync every commit to disk before returning
+	Always,
+	// sync from a background thread at the given interval
+	Interval(Duration),
+	// leave syncing to the operating system. commits are still written on every
+	// store, but only synced on flush
+	Never,
+}
+
+impl Durability {
+	// returns how long a committed measurement may stay unsynced, i.e. the window
+	// of possible data loss on a crash. None means the window is unbounded
+	pub fn get_loss_window(&self) -> Option<Duration> {
+		match *self {
+			Durability::Always => Some(Duration::from_secs(0)),
+			Durability::Interval(interval) => Some(interval),
+			Durability::Never => None,
+		}
+	}
+}
+
+// Describes the measurements that were committed but not yet synced to disk
+// before a flush, i.e. the measurements that would have been lost on a crash
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlushReport {
+	pub measurements: u64,
+	pub bytes: u64,
+	// the time since the oldest unsynced measurement was committed
+	pub max_age: Option<Duration>,
+}
+
+impl FlushReport {
+	pub fn merge(&mut self, other: &FlushReport) {
+		self.measurements += other.measurements;
+		self.bytes += other.bytes;
+		self.max_age = match (self.max_age, other.max_age) {
+			(Some(a), Some(b)) => Some(a.max(b)),
+			(a, b) => a.or(b),
+		};
+	}
+}
+
+// The flusher syncs all logfiles from a background thread at a fixed interval
+// in the Interval mode. Once the flusher is dropped, it does a final flush, so
+// that unsynced commits are not lost on a clean shutdown in any mode.
+#[derive(Debug)]
+pub struct Flusher {
+	logfile_map: LogfileMap,
+	task: Option<PeriodicTask>,
+}
+
+impl Flusher {
+	pub fn start(logfile_map: LogfileMap, durability: Durability) -> Result<Flusher, ::Error> {
+		let task = match durability {
+			Durability::Interval(interval) => {
+				let logfile_map = logfile_map.clone();
+				Some(PeriodicTask::start(
+					"sensorlog-flusher",
+					interval,
+					move || {
+						if let Err(e) = logfile_map.flush() {
+							error!("Error while flushing logfiles: {}", e);
+						}
+					},
+				)?)
+			}
+			_ => None,
+		};
+
+		Ok(Flusher { logfile_map, task })
+	}
+}
+
+impl Drop for Flusher {
+	fn drop(&mut self) {
+		// stop the background thread before the final flush
+		self.task.take();
+
+		if let Err(e) = self.logfile_map.flush() {
+			error!("Error while flushing logfiles: {}", e);
+		}
+	}
+}
@@ -26,6 +26,8 @@ pub enum ErrorCode {
 	InternalServerError,
 	BadRequest,
 	QuotaError,
+	CorruptionError,
+	ClockError,
 }
 
 #[derive(Debug)]
@@ -49,6 +51,16 @@ macro_rules! err_quota {
 	($($arg:tt)*) => (::Error::new(&format!($($arg)*), ::ErrorCode::QuotaError))
 }
 
+#[allow(unused_macros)]
+macro_rules! err_corrupt {
+	($($arg:tt)*) => (::Error::new(&format!($($arg)*), ::ErrorCode::CorruptionError))
+}
+
+#[allow(unused_macros)]
+macro_rules! err_clock {
+	($($arg:tt)*) => (::Error::new(&format!($($arg)*), ::ErrorCode::ClockError))
+}
+
 #[allow(unused_macros)]
 macro_rules! fatal {
 	($($arg:tt)*) => ({
@@ -0,0 +1,97 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use logfile::LogfileUsage;
+use std::fmt::Debug;
+
+// decides which logfile gives up its oldest partition when the total storage of
+// the data directory exceeds the storage budget, see
+// LogfileMap::enforce_storage_limits
+pub trait EvictionPolicy: Debug + Send + Sync {
+	// returns the index of the logfile whose oldest partition is evicted next,
+	// or None if nothing should be evicted. the usage is updated after every
+	// eviction
+	fn select(&self, usage: &[LogfileUsage], budget_bytes: u64) -> Option<usize>;
+}
+
+// evicts the oldest partition across all logfiles
+#[derive(Debug, Clone, Default)]
+pub struct OldestFirstPolicy;
+
+impl OldestFirstPolicy {
+	pub fn new() -> OldestFirstPolicy {
+		OldestFirstPolicy
+	}
+}
+
+impl EvictionPolicy for OldestFirstPolicy {
+	fn select(&self, usage: &[LogfileUsage], _budget_bytes: u64) -> Option<usize> {
+		select_oldest(usage)
+	}
+}
+
+// splits the budget into equal shares for all logfiles that store data and
+// evicts from the logfile that is furthest over its share. once no logfile is
+// over its share, the oldest partition is evicted
+#[derive(Debug, Clone, Default)]
+pub struct ProportionalSharePolicy;
+
+impl ProportionalSharePolicy {
+	pub fn new() -> ProportionalSharePolicy {
+		ProportionalSharePolicy
+	}
+}
+
+impl EvictionPolicy for ProportionalSharePolicy {
+	fn select(&self, usage: &[LogfileUsage], budget_bytes: u64) -> Option<usize> {
+		let logfile_count = usage.iter().filter(|u| u.partitions > 0).count() as u64;
+		if logfile_count == 0 {
+			return None;
+		}
+
+		let share_bytes = budget_bytes / logfile_count;
+
+		let mut selected: Option<usize> = None;
+		for (idx, u) in usage.iter().enumerate() {
+			if u.partitions == 0 || u.bytes <= share_bytes {
+				continue;
+			}
+
+			selected = match selected {
+				Some(s) if usage[s].bytes >= u.bytes => Some(s),
+				_ => Some(idx),
+			};
+		}
+
+		match selected {
+			Some(idx) => Some(idx),
+			None => select_oldest(usage),
+		}
+	}
+}
+
+fn select_oldest(usage: &[LogfileUsage]) -> Option<usize> {
+	usage
+		.iter()
+		.enumerate()
+		.filter(|(_, u)| u.partitions > 0)
+		.min_by_key(|(_, u)| u.oldest_partition_time)
+		.map(|(idx, _)| idx)
+}
@@ -0,0 +1,66 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use measure::Measurement;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FetchDirection {
+	// newest measurements first
+	#[default]
+	Descending,
+	// oldest measurements first
+	Ascending,
+}
+
+// Options for fetching measurements. Only measurements with a time value in the
+// range (time_limit, time_start] are returned, regardless of the direction. If a
+// continuation token from a previous result is given, the fetch resumes exactly
+// after the last measurement returned by the previous fetch. The max_bytes option
+// limits the total encoded size of the returned measurements.
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+	pub time_start: Option<u64>,
+	pub time_limit: Option<u64>,
+	pub limit: Option<u64>,
+	pub max_bytes: Option<u64>,
+	pub direction: FetchDirection,
+	pub continuation: Option<String>,
+}
+
+impl FetchOptions {
+	pub fn new() -> FetchOptions {
+		Default::default()
+	}
+}
+
+// The result of a fetch. If the fetch was stopped before all matching
+// measurements were returned, the continuation token can be passed in the
+// FetchOptions of the next fetch to retrieve the next page. The truncated flag is
+// set if the fetch was stopped because the max_bytes budget was reached or if
+// measurements were skipped because they exceed the max_bytes budget on their own.
+#[derive(Debug, Clone, Default)]
+pub struct FetchResult {
+	pub measurements: Vec<Measurement>,
+	pub continuation: Option<String>,
+	pub truncated: bool,
+	// the number of measurements that were skipped because they are larger than
+	// max_bytes
+	pub skipped: u64,
+}
@@ -0,0 +1,86 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use std::path::Path;
+use storage_backend::StorageBackend;
+
+// atomically replaces the file at path by writing the data to a swap file first
+// and then renaming the swap file over the target path. if sync is true, the swap
+// file is synced before the rename and the parent directory is synced after the
+// rename, so the new file is durable once this function returns
+pub fn write_file_atomic(
+	backend: &dyn StorageBackend,
+	path: &Path,
+	data: &[u8],
+	sync: bool,
+) -> Result<(), ::Error> {
+	let path_swap = format!(
+		"{}.swap",
+		match path.to_str() {
+			Some(v) => v,
+			None => return Err(err_server!("invalid path: {:?}", path)),
+		}
+	);
+
+	// write to swap file
+	backend.write(Path::new(&path_swap), data)?;
+	if sync {
+		backend.sync(Path::new(&path_swap))?;
+	}
+
+	// replace target file with swap file
+	backend.rename(Path::new(&path_swap), path)?;
+
+	// make the rename durable
+	if sync {
+		sync_parent_dir(backend, path)?;
+	}
+
+	Ok(())
+}
+
+pub fn sync_parent_dir(backend: &dyn StorageBackend, path: &Path) -> Result<(), ::Error> {
+	match path.parent() {
+		Some(parent) if parent.as_os_str().is_empty() => backend.sync_dir(Path::new(".")),
+		Some(parent) => backend.sync_dir(parent),
+		None => Err(err_server!("path has no parent directory: {:?}", path)),
+	}
+}
+
+// creates a directory and its parents and syncs the parent directories, so that
+// the new directory entries are durable
+pub fn create_dir_all_synced(backend: &dyn StorageBackend, path: &Path) -> Result<(), ::Error> {
+	if backend.is_dir(path) {
+		return Ok(());
+	}
+
+	if let Some(parent) = path.parent() {
+		if !parent.as_os_str().is_empty() {
+			create_dir_all_synced(backend, parent)?;
+		}
+	}
+
+	backend.create_dir(path)?;
+
+	match path.parent() {
+		Some(_) => sync_parent_dir(backend, path),
+		None => Ok(()),
+	}
+}
@@ -30,94 +30,374 @@ extern crate serde;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
+extern crate base64;
+extern crate libc;
 extern crate md5;
 
 #[macro_use]
 pub mod error;
+pub mod checksum;
+pub mod clock_watchdog;
+pub mod datadir_lock;
+pub mod durability;
+pub mod eviction;
+pub mod fetch;
+pub mod fs_util;
 pub mod logfile;
 pub mod logfile_config;
+pub mod logfile_cursor;
 pub mod logfile_directory;
+pub mod logfile_fsck;
 pub mod logfile_id;
 pub mod logfile_map;
 pub mod logfile_partition;
 pub mod logfile_reader;
+pub mod logfile_recovery;
 pub mod logfile_transaction;
 pub mod logfile_writer;
 pub mod measure;
+pub mod periodic_task;
 pub mod quota;
+pub mod quota_rule;
+pub mod retention;
+pub mod storage_backend;
+pub mod storage_fault;
+pub mod storage_memory;
 pub mod time;
 
+use clock_watchdog::ClockWatchdog;
+use durability::{FlushReport, Flusher};
 use error::{Error, ErrorCode};
+use fetch::{FetchOptions, FetchResult};
 use logfile_config::LogfileConfig;
+use logfile_cursor::MeasurementCursor;
 use logfile_directory::LogfileDirectory;
 use logfile_id::LogfileID;
 use logfile_map::LogfileMap;
+use logfile_reader::LogfileReader;
 use measure::Measurement;
+use retention::RetentionSweeper;
 use std::path::Path;
+use std::sync::Arc;
+use storage_backend::{FsStorageBackend, StorageBackend};
+use time::Clock;
 
 #[derive(Clone, Debug)]
 pub struct Sensorlog {
 	pub logfile_map: LogfileMap,
+	clock: Arc<dyn Clock>,
+	clock_watchdog: Option<ClockWatchdog>,
+	// the flusher and the retention sweeper are only held to keep their
+	// background threads running and to flush on shutdown
+	#[allow(dead_code)]
+	flusher: Option<Arc<Flusher>>,
+	#[allow(dead_code)]
+	retention_sweeper: Option<Arc<RetentionSweeper>>,
+}
+
+// The outcome of a store_measurements batch. The measurements of each sensor are
+// committed separately, so a failure only affects the sensor it occurred for.
+#[derive(Debug, Default)]
+#[must_use]
+pub struct BatchReport {
+	// the number of sensors whose measurements were all stored
+	pub sensors_stored: u64,
+	// the sensors for which a measurement could not be stored, in the order in
+	// which they first appear in the batch. the measurements of the sensor before
+	// the failed measurement were committed, the remaining ones were discarded
+	pub sensors_failed: Vec<(String, Error)>,
+}
+
+impl BatchReport {
+	pub fn is_ok(&self) -> bool {
+		self.sensors_failed.is_empty()
+	}
 }
 
 impl Sensorlog {
-	pub fn new(datadir: &Path, logfile_config: LogfileConfig) -> Result<Self, ::Error> {
-		if !datadir.exists() {
+	pub fn new(
+		datadir: &Path,
+		logfile_config: LogfileConfig,
+		clock: Arc<dyn Clock>,
+	) -> Result<Self, ::Error> {
+		Self::with_backend(
+			Arc::new(FsStorageBackend::new()),
+			datadir,
+			logfile_config,
+			clock,
+		)
+	}
+
+	// opens the data directory on the given storage backend, e.g. a
+	// storage_memory::MemoryStorageBackend to keep all measurements in memory
+	pub fn with_backend(
+		backend: Arc<dyn StorageBackend>,
+		datadir: &Path,
+		logfile_config: LogfileConfig,
+		clock: Arc<dyn Clock>,
+	) -> Result<Self, ::Error> {
+		// volatile backends start out empty, so the data directory is created
+		if !backend.is_volatile() && !backend.is_dir(datadir) {
 			return Err(err_user!("data directory does not exist: {:?}", datadir));
 		}
 
-		let logfile_directory = LogfileDirectory::open(&datadir)?;
-		let logfile_map = LogfileMap::open(logfile_directory, logfile_config)?;
+		// lock the data directory before anything is written to it
+		let logfile_directory = LogfileDirectory::open(backend.clone(), datadir)?;
+
+		let clock_watchdog = match logfile_config.get_clock_watchdog() {
+			Some(config) => Some(ClockWatchdog::open(
+				backend,
+				datadir,
+				config,
+				clock.clone(),
+			)?),
+			None => None,
+		};
+
+		let durability = logfile_config.get_durability();
+		let retention_sweep_interval = if logfile_config.has_max_age() {
+			Some(logfile_config.get_retention_sweep_interval())
+		} else {
+			None
+		};
+
+		let logfile_map = LogfileMap::open(logfile_directory, logfile_config, clock.clone())?;
+
+		let flusher = Some(Arc::new(Flusher::start(logfile_map.clone(), durability)?));
+
+		let retention_sweeper = match retention_sweep_interval {
+			Some(interval) => Some(Arc::new(RetentionSweeper::start(
+				logfile_map.clone(),
+				interval,
+			)?)),
+			None => None,
+		};
+
+		let service = Self {
+			logfile_map,
+			clock,
+			clock_watchdog,
+			flusher,
+			retention_sweeper,
+		};
+
+		Ok(service)
+	}
+
+	// opens the data directory for reading only. nothing is created or written
+	// and the data directory is not locked, so it can be inspected while another
+	// process is writing to it. measurements committed by the writing process
+	// become visible on the next fetch
+	pub fn open_read_only(datadir: &Path) -> Result<Self, ::Error> {
+		let logfile_directory =
+			LogfileDirectory::open_read_only(Arc::new(FsStorageBackend::new()), datadir)?;
+		let clock: Arc<dyn Clock> = Arc::new(time::SystemClock);
+		let logfile_map = LogfileMap::open(logfile_directory, LogfileConfig::new(), clock.clone())?;
 
-		let service = Self { logfile_map };
+		let service = Self {
+			logfile_map,
+			clock,
+			clock_watchdog: None,
+			flusher: None,
+			retention_sweeper: None,
+		};
 
 		Ok(service)
 	}
 
-	pub fn store_measurement(
+	pub fn store_measurement<D: AsRef<[u8]>>(
 		&self,
 		time: Option<u64>,
 		sensor_id: &str,
-		data: &str,
+		data: D,
 	) -> Result<(), ::Error> {
 		debug!("Storing measurement: sensor_id={}", sensor_id);
+		self.check_clock()?;
 
 		let measurement = Measurement {
-			time: time.unwrap_or(::time::get_unix_microseconds()?),
-			data: data.to_string(),
+			time: match time {
+				Some(time) => time,
+				None => self.clock.get_unix_microseconds()?,
+			},
+			data: data.as_ref().to_vec(),
 		};
 
 		let logfile_id = LogfileID::from_string(sensor_id.to_owned());
-		let logfile = self.logfile_map.lookup_or_create(&logfile_id)?;
-		logfile.append_measurement(&measurement)?;
+		self.logfile_map
+			.append_measurements(&logfile_id, ::std::slice::from_ref(&measurement))?;
 
+		self.enforce_storage_limits();
 		Ok(())
 	}
 
+	// stores a batch of (time, sensor_id, data) measurements. the measurements
+	// for each sensor are appended in order and committed once per logfile. all
+	// sensors are attempted, even if storing the measurements of an earlier
+	// sensor failed; the returned report lists the sensors that failed
+	pub fn store_measurements<D: AsRef<[u8]>>(
+		&self,
+		batch: &[(Option<u64>, &str, D)],
+	) -> Result<BatchReport, ::Error> {
+		debug!("Storing measurement batch: size={}", batch.len());
+		self.check_clock()?;
+
+		let now = self.clock.get_unix_microseconds()?;
+
+		// group the measurements by sensor, keeping the order in which the sensors
+		// first appear in the batch
+		let mut groups = Vec::<(&str, Vec<Measurement>)>::new();
+		for &(time, sensor_id, ref data) in batch {
+			let measurement = Measurement {
+				time: time.unwrap_or(now),
+				data: data.as_ref().to_vec(),
+			};
+
+			match groups.iter_mut().find(|g| g.0 == sensor_id) {
+				Some(group) => group.1.push(measurement),
+				None => groups.push((sensor_id, vec![measurement])),
+			}
+		}
+
+		let mut report = BatchReport::default();
+		for (sensor_id, measurements) in groups {
+			let logfile_id = LogfileID::from_string(sensor_id.to_owned());
+			match self
+				.logfile_map
+				.append_measurements(&logfile_id, &measurements)
+			{
+				Ok(()) => report.sensors_stored += 1,
+				Err(e) => {
+					warn!(
+						"Error while storing measurements: sensor_id={}: {}",
+						sensor_id, e
+					);
+					report.sensors_failed.push((sensor_id.to_owned(), e));
+				}
+			}
+		}
+
+		self.enforce_storage_limits();
+		Ok(report)
+	}
+
+	// the measurements were already committed, so a failed eviction does not fail
+	// the store. the evicted partitions are deleted with the next commit of their
+	// logfile
+	fn enforce_storage_limits(&self) {
+		if let Err(e) = self.logfile_map.enforce_storage_limits() {
+			error!("Error while enforcing the storage limits: {}", e);
+		}
+	}
+
 	pub fn fetch_measurements(
 		&self,
 		sensor_id: &str,
-		time_start: Option<u64>,
-		time_limit: Option<u64>,
-		limit: Option<u64>,
-	) -> Result<Vec<Measurement>, ::Error> {
+		options: &FetchOptions,
+	) -> Result<FetchResult, ::Error> {
 		let logfile_id = LogfileID::from_string(sensor_id.to_owned());
 
 		debug!(
-			"Fetching measurements: sensor_id={}; time_start={:?} time_limit={:?} limit={:?}",
-			sensor_id, time_start, time_limit, limit
+			"Fetching measurements: sensor_id={}; options={:?}",
+			sensor_id, options
 		);
 
-		let measurements = match self.logfile_map.lookup(&logfile_id) {
-			Some(logfile) => logfile.fetch_measurements(time_start, time_limit, limit)?,
-			None => Vec::<Measurement>::new(),
-		};
+		self.check_clock()?;
 
-		Ok(measurements)
+		match self.logfile_map.lookup_snapshot(&logfile_id)? {
+			Some(logfile) => logfile.fetch_measurements(options),
+			None => LogfileReader::new(&[], None).fetch_measurements(options),
+		}
 	}
 
-	pub fn set_storage_quota_for(&mut self, sensor_id: &str, quota: ::quota::StorageQuota) {
+	pub fn fetch_cursor(
+		&self,
+		sensor_id: &str,
+		options: &FetchOptions,
+	) -> Result<MeasurementCursor, ::Error> {
+		let logfile_id = LogfileID::from_string(sensor_id.to_owned());
+
+		debug!(
+			"Opening measurement cursor: sensor_id={}; options={:?}",
+			sensor_id, options
+		);
+
+		self.check_clock()?;
+
+		match self.logfile_map.lookup_snapshot(&logfile_id)? {
+			Some(logfile) => logfile.fetch_cursor(options),
+			None => MeasurementCursor::open(Vec::new(), None, options),
+		}
+	}
+
+	// changes the storage quota of the sensor. the new quota is persisted in the
+	// data directory and overrides the quota from the config on later restarts. if
+	// the stored measurements exceed the new quota, the oldest measurements are
+	// dropped right away
+	pub fn set_storage_quota_for(
+		&self,
+		sensor_id: &str,
+		quota: ::quota::StorageQuota,
+	) -> Result<(), ::Error> {
 		let logfile_id = LogfileID::from_string(sensor_id.to_string());
 		self.logfile_map.set_storage_quota_for(&logfile_id, quota)
 	}
+
+	// returns the storage quota of the sensor and the quota rule it comes from
+	pub fn explain_storage_quota_for(&self, sensor_id: &str) -> ::quota_rule::QuotaExplanation {
+		let logfile_id = LogfileID::from_string(sensor_id.to_string());
+		self.logfile_map.explain_storage_quota_for(&logfile_id)
+	}
+
+	// deletes the sensor and all of its measurements. after a crash, the sensor
+	// is either fully present or fully deleted
+	pub fn delete_sensor(&self, sensor_id: &str) -> Result<(), ::Error> {
+		debug!("Deleting sensor: sensor_id={}", sensor_id);
+
+		let logfile_id = LogfileID::from_string(sensor_id.to_string());
+		self.logfile_map.delete(&logfile_id)
+	}
+
+	// deletes the measurements of the sensor with a time value in the range
+	// [from, until)
+	pub fn delete_range(&self, sensor_id: &str, from: u64, until: u64) -> Result<(), ::Error> {
+		debug!(
+			"Deleting measurements: sensor_id={}; from={}; until={}",
+			sensor_id, from, until
+		);
+
+		let logfile_id = LogfileID::from_string(sensor_id.to_string());
+		self.logfile_map.delete_range(&logfile_id, from, until)
+	}
+
+	// drops the measurements that are older than the max age of their sensor.
+	// this is done periodically in the background if a max age is configured
+	pub fn expire_measurements(&self) -> Result<(), ::Error> {
+		debug!("Expiring measurements");
+		self.logfile_map.expire()
+	}
+
+	// returns the storage used by each sensor, ordered by sensor id
+	pub fn get_storage_usage(&self) -> Vec<::logfile::LogfileUsage> {
+		self.logfile_map.get_usage()
+	}
+
+	// returns the storage used by the members of each quota group
+	pub fn get_group_usage(&self) -> Vec<::quota_rule::QuotaGroupUsage> {
+		self.logfile_map.get_group_usage()
+	}
+
+	// syncs all committed measurements to disk. the returned report describes the
+	// measurements that were not synced before the flush, i.e. the measurements
+	// that would have been lost on a crash
+	pub fn flush(&self) -> Result<FlushReport, ::Error> {
+		debug!("Flushing measurements");
+		self.logfile_map.flush()
+	}
+
+	fn check_clock(&self) -> Result<(), ::Error> {
+		match self.clock_watchdog {
+			Some(ref clock_watchdog) => clock_watchdog.check(&self.logfile_map),
+			None => Ok(()),
+		}
+	}
 }
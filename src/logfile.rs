@@ -18,18 +18,28 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
-use logfile_config::LogfileConfig;
+use durability::{Durability, FlushReport};
+use fetch::{FetchOptions, FetchResult};
+use logfile_config::{LogfileConfig, OutOfOrderPolicy};
+use logfile_cursor::MeasurementCursor;
 use logfile_id::LogfileID;
-use logfile_partition::LogfilePartition;
+use logfile_partition::{self, LogfilePartition};
 use logfile_reader::LogfileReader;
-use logfile_transaction::LogfileTransaction;
+use logfile_recovery::RecoveryReport;
+use logfile_transaction::{LogfileTransaction, LogfileTransactionPartition};
 use measure::Measurement;
 use quota::StorageQuota;
-use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use storage_backend::StorageBackend;
+use time::Clock;
 
-const TRANSACTION_FILE_NAME: &str = "tx.lock";
+pub const TRANSACTION_FILE_NAME: &str = "tx.lock";
+
+// the sensor id is also stored in a sidecar file, so that a lost transaction file
+// can be rebuilt, see logfile_fsck
+pub const SENSOR_ID_FILE_NAME: &str = "sensor_id";
 
 #[derive(Debug, Clone)]
 pub struct Logfile {
@@ -38,45 +48,116 @@ pub struct Logfile {
 
 #[derive(Debug, Clone)]
 pub struct LogfileStorage {
+	backend: Arc<dyn StorageBackend>,
+	clock: Arc<dyn Clock>,
 	id: LogfileID,
 	path: PathBuf,
 	storage_quota: StorageQuota,
+	max_age: Option<Duration>,
+	out_of_order_policy: OutOfOrderPolicy,
 	partitions: Vec<LogfilePartition>,
 	partitions_deleted: Vec<LogfilePartition>,
 	partition_size_bytes: u64,
+	late_partition: Option<LogfilePartition>,
+	next_sequence: u64,
+	// set if partition files were created or deleted since the logfile
+	// directory was last synced
+	directory_dirty: bool,
+	durability: Durability,
+	unsynced: bool,
+	unsynced_measurements: u64,
+	unsynced_bytes: u64,
+	unsynced_since: Option<Instant>,
+	// set once the logfile was deleted. handles to a deleted logfile can still
+	// be held by concurrent callers, but no longer store anything
+	deleted: bool,
+}
+
+// the storage used by a single logfile, see LogfileMap::get_usage
+#[derive(Debug, Clone)]
+pub struct LogfileUsage {
+	pub id: LogfileID,
+	pub bytes: u64,
+	pub partitions: u64,
+	// the time of the newest measurement in the partition that is evicted next
+	pub oldest_partition_time: Option<u64>,
 }
 
 impl Logfile {
-	pub fn create(id: LogfileID, path: &Path, config: &LogfileConfig) -> Result<Logfile, ::Error> {
+	pub fn create(
+		backend: &Arc<dyn StorageBackend>,
+		clock: &Arc<dyn Clock>,
+		id: LogfileID,
+		path: &Path,
+		config: &LogfileConfig,
+	) -> Result<Logfile, ::Error> {
 		let storage_quota = config.get_storage_quota_for(&id);
 		if storage_quota.is_zero() {
 			return Err(err_quota!("insufficient quota"));
 		}
 
 		debug!("Creating new logfile; id={:?}", id);
-		fs::create_dir_all(path)?;
+		::fs_util::create_dir_all_synced(&**backend, path)?;
+
+		::fs_util::write_file_atomic(
+			&**backend,
+			&path.join(SENSOR_ID_FILE_NAME),
+			id.get_string().as_bytes(),
+			true,
+		)?;
+
+		let mut storage = LogfileStorage {
+			backend: backend.clone(),
+			clock: clock.clone(),
+			id: id.clone(),
+			path: path.to_owned(),
+			storage_quota,
+			max_age: config.get_max_age_for(&id),
+			out_of_order_policy: config.get_out_of_order_policy_for(&id),
+			partitions: Vec::<LogfilePartition>::new(),
+			partitions_deleted: Vec::<LogfilePartition>::new(),
+			partition_size_bytes: config.get_partition_size_for(&id),
+			late_partition: None,
+			next_sequence: 0,
+			directory_dirty: false,
+			durability: config.get_durability(),
+			unsynced: false,
+			unsynced_measurements: 0,
+			unsynced_bytes: 0,
+			unsynced_since: None,
+			deleted: false,
+		};
+
+		// write an empty transaction, so that a logfile directory without a
+		// transaction file can be detected as damaged
+		storage.write_transaction()?;
 
 		let logfile = Logfile {
-			storage: Arc::new(RwLock::new(LogfileStorage {
-				id: id.clone(),
-				path: path.to_owned(),
-				storage_quota,
-				partitions: Vec::<LogfilePartition>::new(),
-				partitions_deleted: Vec::<LogfilePartition>::new(),
-				partition_size_bytes: config.get_partition_size_for(&id),
-			})),
+			storage: Arc::new(RwLock::new(storage)),
 		};
 
 		Ok(logfile)
 	}
 
-	pub fn open(path: &Path, config: &LogfileConfig) -> Result<Option<Logfile>, ::Error> {
+	pub fn open(
+		backend: &Arc<dyn StorageBackend>,
+		clock: &Arc<dyn Clock>,
+		path: &Path,
+		config: &LogfileConfig,
+	) -> Result<Option<Logfile>, ::Error> {
 		let transaction_path = path.join(TRANSACTION_FILE_NAME).to_owned();
-		if !transaction_path.exists() {
+		if !backend.exists(&transaction_path) {
+			if has_partition_files(&**backend, path)? {
+				return Err(err_corrupt!(
+					"missing transaction file for logfile at {:?}",
+					path
+				));
+			}
+
 			return Ok(None);
 		}
 
-		let transaction = LogfileTransaction::read_file(&transaction_path)?;
+		let transaction = LogfileTransaction::read_file(&**backend, &transaction_path)?;
 
 		debug!("Loading logfile; id={:?}", transaction.id);
 
@@ -85,21 +166,56 @@ impl Logfile {
 
 		for partition in transaction.partitions {
 			logfile_partitions.push(LogfilePartition::open(
+				backend,
 				path,
+				partition.sequence,
 				partition.time_head,
 				partition.time_tail,
 				partition.offset,
 			));
 		}
 
+		let late_partition = match transaction.late_partition {
+			Some(partition) => Some(LogfilePartition::open_late(
+				backend,
+				path,
+				get_late_partition_sequence(&partition)?,
+				partition.time_head,
+				partition.time_tail,
+				partition.offset,
+			)),
+			None => None,
+		};
+
+		// never reuse the sequence number of a committed partition
+		let next_sequence = logfile_partitions
+			.iter()
+			.chain(late_partition.iter())
+			.filter_map(|p| p.get_sequence())
+			.map(|sequence| sequence + 1)
+			.fold(transaction.next_sequence, u64::max);
+
 		let logfile = Logfile {
 			storage: Arc::new(RwLock::new(LogfileStorage {
+				backend: backend.clone(),
+				clock: clock.clone(),
 				id: logfile_id.clone(),
 				path: path.to_owned(),
 				storage_quota: config.get_storage_quota_for(&logfile_id),
+				max_age: config.get_max_age_for(&logfile_id),
+				out_of_order_policy: config.get_out_of_order_policy_for(&logfile_id),
 				partitions: logfile_partitions,
 				partitions_deleted: Vec::<LogfilePartition>::new(),
 				partition_size_bytes: config.get_partition_size_for(&logfile_id),
+				late_partition,
+				next_sequence,
+				directory_dirty: false,
+				durability: config.get_durability(),
+				unsynced: false,
+				unsynced_measurements: 0,
+				unsynced_bytes: 0,
+				unsynced_since: None,
+				deleted: false,
 			})),
 		};
 
@@ -116,76 +232,367 @@ impl Logfile {
 	}
 
 	pub fn append_measurement(&self, measurement: &Measurement) -> Result<(), ::Error> {
-		let measurement_size = measurement.get_encoded_size() as u64;
+		self.append_measurements(::std::slice::from_ref(measurement))
+	}
 
+	// appends all measurements and commits the transaction once. if one of the
+	// measurements can not be stored, the measurements before it are committed
+	// and the error is returned
+	pub fn append_measurements(&self, measurements: &[Measurement]) -> Result<(), ::Error> {
 		// lock the storage
 		let mut storage_locked = match self.storage.write() {
 			Ok(l) => l,
 			Err(_) => fatal!("lock is poisoned"),
 		};
 
+		if storage_locked.deleted {
+			return Err(err_user!("sensor {:?} was deleted", storage_locked.id));
+		}
+
+		for measurement in measurements {
+			if let Err(e) = storage_locked.append_measurement(measurement) {
+				storage_locked.commit()?;
+				return Err(e);
+			}
+		}
+
+		// commit the transaction to disk
+		storage_locked.commit()
+	}
+
+	pub fn clear(&self) -> Result<(), ::Error> {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if storage_locked.deleted {
+			return Ok(());
+		}
+
+		storage_locked.clear()?;
+		storage_locked.commit()
+	}
+
+	// changes the storage quota of the logfile. if the stored measurements exceed
+	// the new quota, the oldest partitions are dropped right away
+	pub fn set_storage_quota(&self, quota: StorageQuota) -> Result<(), ::Error> {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if storage_locked.deleted {
+			return Ok(());
+		}
+
+		debug!(
+			"Changing storage quota; id={:?}, quota={:?}",
+			storage_locked.id, quota
+		);
+
+		storage_locked.storage_quota = quota;
+
+		// a zero quota can not hold any data, not even an empty partition
+		if storage_locked.storage_quota.is_zero() {
+			storage_locked.clear()?;
+		} else {
+			storage_locked.garbage_collect(0)?;
+		}
+
+		if storage_locked.partitions_deleted.is_empty() {
+			return Ok(());
+		}
+
+		storage_locked.commit()
+	}
+
+	// drops the partitions that are older than the max age
+	pub fn expire(&self) -> Result<(), ::Error> {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if storage_locked.deleted {
+			return Ok(());
+		}
+
+		storage_locked.expire()?;
+
+		if storage_locked.partitions_deleted.is_empty() {
+			return Ok(());
+		}
+
+		storage_locked.commit()
+	}
+
+	// deletes the measurements with a time value in the range [from, until) and
+	// commits the result in a single transaction
+	pub fn delete_range(&self, from: u64, until: u64) -> Result<(), ::Error> {
+		if from >= until {
+			return Err(err_user!("invalid time range: [{}, {})", from, until));
+		}
+
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if storage_locked.deleted {
+			return Err(err_user!("sensor {:?} was deleted", storage_locked.id));
+		}
+
+		storage_locked.delete_range(from, until)?;
+		storage_locked.commit()
+	}
+
+	// marks the logfile as deleted and drops its partitions from memory. the
+	// files are deleted by the caller, see LogfileDirectory::delete_logfile
+	pub fn delete(&self) {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		storage_locked.deleted = true;
+		storage_locked.partitions.clear();
+		storage_locked.partitions_deleted.clear();
+		storage_locked.late_partition = None;
+		storage_locked.unsynced = false;
+	}
+
+	pub fn get_usage(&self) -> LogfileUsage {
+		let storage_locked = match self.storage.read() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		storage_locked.get_usage()
+	}
+
+	// drops the oldest partition to free space for other logfiles. the
+	// transaction is not committed, see LogfileMap::enforce_storage_limits
+	pub fn evict_partition(&self) -> u64 {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if storage_locked.deleted {
+			return 0;
+		}
+
+		storage_locked.evict_partition()
+	}
+
+	// commits pending partition deletions, if any
+	pub fn commit(&self) -> Result<(), ::Error> {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if storage_locked.deleted || storage_locked.partitions_deleted.is_empty() {
+			return Ok(());
+		}
+
+		storage_locked.commit()
+	}
+
+	// reconciles the logfile directory with the committed transaction, see
+	// logfile_recovery
+	pub fn recover(&self) -> Result<RecoveryReport, ::Error> {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		let report = {
+			let storage = &mut *storage_locked;
+			let mut partitions = storage
+				.partitions
+				.iter_mut()
+				.chain(storage.late_partition.iter_mut())
+				.collect::<Vec<_>>();
+
+			::logfile_recovery::recover(&*storage.backend, &storage.path, &mut partitions)?
+		};
+
+		// commit the partitions that were clamped after data was lost
+		if report.partitions_incomplete > 0 {
+			storage_locked.write_transaction()?;
+		}
+
+		// partitions that were created before sequence numbers were introduced
+		storage_locked.migrate_partitions()?;
+
+		// logfiles that were created before the sidecar file was introduced
+		let sensor_id_path = storage_locked.path.join(SENSOR_ID_FILE_NAME);
+		if !storage_locked.backend.exists(&sensor_id_path) {
+			::fs_util::write_file_atomic(
+				&*storage_locked.backend,
+				&sensor_id_path,
+				storage_locked.id.get_string().as_bytes(),
+				true,
+			)?;
+		}
+
+		Ok(report)
+	}
+
+	// syncs all committed measurements to disk and reports the measurements that
+	// were not synced before
+	pub fn flush(&self) -> Result<FlushReport, ::Error> {
+		let mut storage_locked = match self.storage.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		storage_locked.flush()
+	}
+
+	pub fn fetch_measurements(&self, options: &FetchOptions) -> Result<FetchResult, ::Error> {
+		// the partitions are read without holding the lock, see fetch_cursor
+		let (partitions, late_partition) = match self.storage.read() {
+			Ok(l) => (l.partitions.clone(), l.late_partition.clone()),
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		let reader = LogfileReader::new(&partitions, late_partition.as_ref());
+		reader.fetch_measurements(options)
+	}
+
+	pub fn fetch_cursor(&self, options: &FetchOptions) -> Result<MeasurementCursor, ::Error> {
+		let (partitions, late_partition) = match self.storage.read() {
+			Ok(l) => (l.partitions.clone(), l.late_partition.clone()),
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		MeasurementCursor::open(partitions, late_partition, options)
+	}
+}
+
+impl LogfileStorage {
+	// appends the measurement without committing the transaction
+	pub fn append_measurement(&mut self, measurement: &Measurement) -> Result<(), ::Error> {
+		let measurement_size = measurement.get_encoded_size();
+
 		// check if the measurement exceeds the total storage quota
-		let quota = storage_locked.storage_quota.clone();
+		let quota = self.storage_quota.clone();
 		if !quota.is_sufficient_bytes(measurement_size) {
 			return Err(err_quota!("insufficient quota"));
 		}
 
 		// check that the measurement time is monotonically increasing
-		let is_monotonic = match storage_locked.partitions.last() {
-			Some(p) => measurement.time >= p.get_time_head(),
+		let time_head = self.partitions.last().map(|p| p.get_time_head());
+		let is_monotonic = match time_head {
+			Some(time_head) => measurement.time >= time_head,
 			None => true,
 		};
 
+		let measurement_clamped;
+		let mut measurement = measurement;
 		if !is_monotonic {
-			warn!(
-				"Clock for sensor {:?} jumped backwards, flushing data...",
-				storage_locked.id
-			);
+			match self.out_of_order_policy {
+				OutOfOrderPolicy::Flush => {
+					warn!(
+						"Clock for sensor {:?} jumped backwards, flushing data...",
+						self.id
+					);
 
-			storage_locked.clear()?;
-			storage_locked.commit()?;
+					self.clear()?;
+				}
+				OutOfOrderPolicy::Reject => {
+					return Err(err_user!(
+						"measurement time values must be monotonically increasing for \
+						 each sensor_id"
+					));
+				}
+				OutOfOrderPolicy::Clamp => {
+					measurement_clamped = Measurement {
+						time: time_head.unwrap_or(measurement.time),
+						data: measurement.data.clone(),
+					};
+
+					measurement = &measurement_clamped;
+				}
+				OutOfOrderPolicy::LateBuffer => {
+					self.append_late_measurement(measurement)?;
+					self.mark_unsynced(measurement_size);
+					return Ok(());
+				}
+			}
 		}
 
 		// allocate storage for the new measurement
-		storage_locked.allocate(measurement_size)?;
+		self.allocate(measurement_size)?;
 
 		// insert the new measurement into the head partition
-		match storage_locked.partitions.last_mut() {
-			Some(p) => p.append_measurement(measurement)?,
+		let result = match self.partitions.last_mut() {
+			Some(p) => p.append_measurement(measurement),
 			None => return Err(err_server!("corrupt partition map")),
 		};
 
-		// commit the transaction to disk
-		storage_locked.commit()
+		// drop the head partition if it was created for the failed append, so
+		// that no partition without a file is committed
+		if let Err(e) = result {
+			if self.partitions.last().map(|p| p.get_file_offset()) == Some(0) {
+				self.partitions.pop();
+			}
+
+			return Err(e);
+		}
+
+		self.mark_unsynced(measurement_size);
+		Ok(())
 	}
 
-	pub fn fetch_measurements(
-		&self,
-		time_start: Option<u64>,
-		time_limit: Option<u64>,
-		limit: Option<u64>,
-	) -> Result<Vec<Measurement>, ::Error> {
-		let storage_locked = match self.storage.read() {
-			Ok(l) => l,
-			Err(_) => fatal!("lock is poisoned"),
-		};
+	fn mark_unsynced(&mut self, bytes: u64) {
+		self.unsynced = true;
+		self.unsynced_measurements += 1;
+		self.unsynced_bytes += bytes;
 
-		let reader = LogfileReader::new(&storage_locked.partitions);
-		reader.fetch_measurements(time_start, time_limit, limit)
+		if self.unsynced_since.is_none() {
+			self.unsynced_since = Some(Instant::now());
+		}
 	}
-}
 
-impl LogfileStorage {
+	// commits the appended measurements and the partition map. a synced commit
+	// consists of the following steps, see "Crash Consistency" in the README:
+	//
+	//   1. sync the data of all partitions that were appended to
+	//   2. sync the logfile directory if partition files were created
+	//   3. write and sync tx.lock.swap
+	//   4. rename tx.lock.swap to tx.lock and sync the logfile directory
+	//   5. delete the partitions that were dropped from the partition map
+	//
+	// a crash before step 4 completes leaves the previous transaction in place.
+	// the data appended after it is discarded on the next open, see
+	// logfile_recovery
+	//
+	// unsynced commits write and rename tx.lock.swap without syncing anything,
+	// so that the commit survives a crash of the process as long as the operating
+	// system writes back its caches. on a power loss, tx.lock may point past the
+	// data that reached the disk; such partitions are clamped to their intact
+	// records on the next open. the next flush syncs the partitions and the
+	// transaction
 	pub fn commit(&mut self) -> Result<(), ::Error> {
-		// write transaction to disk
-		let transaction = LogfileTransaction::new(&self.id, &self.partitions);
+		// transactions that drop partitions are always synced, so that a crash can
+		// not leave a transaction behind that references deleted partitions
+		let sync = self.durability == Durability::Always || !self.partitions_deleted.is_empty();
 
-		let transaction_path = self.path.join(TRANSACTION_FILE_NAME);
-		transaction.write_file(&transaction_path)?;
+		if sync {
+			self.write_transaction()?;
+		} else {
+			self.write_transaction_file(false)?;
+			self.unsynced = true;
+		}
 
 		// drop deleted partitions
 		for partition in &mut self.partitions_deleted {
 			partition.delete()?;
+			self.directory_dirty = true;
 		}
 
 		self.partitions_deleted.clear();
@@ -193,6 +600,99 @@ impl LogfileStorage {
 		Ok(())
 	}
 
+	pub fn flush(&mut self) -> Result<FlushReport, ::Error> {
+		if self.deleted {
+			return Ok(FlushReport::default());
+		}
+
+		let report = FlushReport {
+			measurements: self.unsynced_measurements,
+			bytes: self.unsynced_bytes,
+			max_age: self.unsynced_since.map(|t| t.elapsed()),
+		};
+
+		if self.unsynced {
+			self.write_transaction()?;
+		}
+
+		Ok(report)
+	}
+
+	// syncs the measurements that were appended since the last sync and writes
+	// the transaction
+	fn write_transaction(&mut self) -> Result<(), ::Error> {
+		for partition in &mut self.partitions {
+			partition.sync()?;
+		}
+
+		if let Some(ref mut partition) = self.late_partition {
+			partition.sync()?;
+		}
+
+		// make sure new partition files are found after a crash
+		if self.directory_dirty {
+			self.backend.sync_dir(&self.path)?;
+			self.directory_dirty = false;
+		}
+
+		self.write_transaction_file(true)?;
+
+		self.unsynced = false;
+		self.unsynced_measurements = 0;
+		self.unsynced_bytes = 0;
+		self.unsynced_since = None;
+
+		Ok(())
+	}
+
+	fn write_transaction_file(&self, sync: bool) -> Result<(), ::Error> {
+		let transaction = LogfileTransaction::new(
+			&self.id,
+			&self.partitions,
+			self.late_partition.as_ref(),
+			self.next_sequence,
+		);
+
+		let transaction_path = self.path.join(TRANSACTION_FILE_NAME);
+		transaction.write_file(&*self.backend, &transaction_path, sync)
+	}
+
+	// moves all partitions that use the legacy naming scheme to sequence numbers
+	fn migrate_partitions(&mut self) -> Result<(), ::Error> {
+		let mut next_sequence = self.next_sequence;
+		let mut paths_legacy = Vec::new();
+		let mut migrated = false;
+
+		for partition in &mut self.partitions {
+			if partition.get_sequence().is_some() {
+				continue;
+			}
+
+			if let Some(path_legacy) = partition.migrate(next_sequence)? {
+				paths_legacy.push(path_legacy);
+			}
+
+			next_sequence += 1;
+			migrated = true;
+		}
+
+		if !migrated {
+			return Ok(());
+		}
+
+		self.next_sequence = next_sequence;
+		self.directory_dirty = true;
+
+		// the old file names are deleted once the new names are committed
+		self.write_transaction()?;
+
+		for path_legacy in paths_legacy {
+			self.backend.remove(&path_legacy)?;
+		}
+
+		Ok(())
+	}
+
 	pub fn allocate(&mut self, new_bytes: u64) -> Result<(), ::Error> {
 		// drop partitions from the tail until the quota is met
 		self.garbage_collect(new_bytes)?;
@@ -202,46 +702,406 @@ impl LogfileStorage {
 			Some(partition) => {
 				if partition.get_file_offset() + new_bytes > self.partition_size_bytes {
 					Some(LogfilePartition::create(
+						&self.backend,
 						&self.path,
+						self.next_sequence,
 						partition.get_time_head(),
 					)?)
 				} else {
 					None
 				}
 			}
-			None => Some(LogfilePartition::create(&self.path, 0)?),
+			None => Some(LogfilePartition::create(
+				&self.backend,
+				&self.path,
+				self.next_sequence,
+				0,
+			)?),
 		};
 
 		if let Some(partition) = new_partition {
+			self.next_sequence += 1;
+			self.directory_dirty = true;
 			self.partitions.push(partition);
 		}
 
 		Ok(())
 	}
 
+	pub fn append_late_measurement(&mut self, measurement: &Measurement) -> Result<(), ::Error> {
+		let measurement_size = measurement.get_encoded_size();
+
+		// the late partition is limited to the partition size. once it is full, it
+		// is merged into the regular partitions
+		let late_bytes = match self.late_partition {
+			Some(ref p) => p.get_file_offset(),
+			None => 0,
+		};
+
+		if late_bytes > 0 && late_bytes + measurement_size > self.partition_size_bytes {
+			self.merge_late_partition()?;
+		}
+
+		if measurement_size > self.partition_size_bytes {
+			return Err(err_quota!("late buffer for sensor {:?} is full", self.id));
+		}
+
+		// drop partitions from the tail until the quota is met
+		self.garbage_collect(measurement_size)?;
+
+		if self.late_partition.is_none() {
+			self.late_partition = Some(LogfilePartition::create_late(
+				&self.backend,
+				&self.path,
+				self.next_sequence,
+				measurement.time,
+			)?);
+
+			self.next_sequence += 1;
+			self.directory_dirty = true;
+		}
+
+		let result = match self.late_partition {
+			Some(ref mut p) => p.append_late_measurement(measurement),
+			None => Err(err_server!("corrupt partition map")),
+		};
+
+		// see append_measurement
+		if result.is_err() && self.late_partition.as_ref().map(|p| p.get_file_offset()) == Some(0) {
+			self.late_partition = None;
+		}
+
+		result
+	}
+
+	// merges the measurements of the late partition into the regular partitions
+	// without committing the transaction. the regular partitions that overlap the
+	// time range of the late partition are rewritten to new partition files that
+	// contain the measurements of both in time order. the old partition files and
+	// the late partition are deleted once the transaction is committed
+	pub fn merge_late_partition(&mut self) -> Result<(), ::Error> {
+		let late_partition = match self.late_partition {
+			Some(ref p) => p.clone(),
+			None => return Ok(()),
+		};
+
+		let merge_begin = self
+			.partitions
+			.iter()
+			.position(|p| p.get_time_head() > late_partition.get_time_tail())
+			.unwrap_or(self.partitions.len());
+
+		let merge_end = self.partitions[merge_begin..]
+			.iter()
+			.position(|p| p.get_time_tail() >= late_partition.get_time_head())
+			.map_or(self.partitions.len(), |i| merge_begin + i);
+
+		debug!(
+			"Merging late partition; id={:?}, partitions={}",
+			self.id,
+			merge_end - merge_begin
+		);
+
+		let mut measurements = Vec::<Measurement>::new();
+		for partition in &self.partitions[merge_begin..merge_end] {
+			measurements.append(&mut partition.read_measurements()?);
+		}
+
+		// the sort is stable, so regular measurements stay in front of late
+		// measurements with the same time
+		measurements.append(&mut late_partition.read_measurements()?);
+		measurements.sort_by_key(|m| m.time);
+
+		let mut partitions_merged = Vec::<LogfilePartition>::new();
+		for measurement in &measurements {
+			let is_full = match partitions_merged.last() {
+				Some(p) => {
+					p.get_file_offset() + measurement.get_encoded_size() > self.partition_size_bytes
+				}
+				None => true,
+			};
+
+			if is_full {
+				partitions_merged.push(LogfilePartition::create(
+					&self.backend,
+					&self.path,
+					self.next_sequence,
+					measurement.time,
+				)?);
+
+				self.next_sequence += 1;
+				self.directory_dirty = true;
+			}
+
+			match partitions_merged.last_mut() {
+				Some(p) => p.append_measurement(measurement)?,
+				None => return Err(err_server!("corrupt partition map")),
+			};
+		}
+
+		let partitions_deleted = self
+			.partitions
+			.splice(merge_begin..merge_end, partitions_merged)
+			.collect::<Vec<_>>();
+
+		self.partitions_deleted.extend(partitions_deleted);
+		self.partitions_deleted.push(late_partition);
+		self.late_partition = None;
+		Ok(())
+	}
+
+	// drops the measurements with a time value in the range [from, until) without
+	// committing the transaction. partitions that lie completely within the range
+	// are dropped. partitions that overlap the range are rewritten to new partition
+	// files that only contain the remaining measurements, the old partition files
+	// are deleted once the transaction is committed
+	pub fn delete_range(&mut self, from: u64, until: u64) -> Result<(), ::Error> {
+		let mut partitions = Vec::<LogfilePartition>::new();
+		let mut partitions_deleted = Vec::<LogfilePartition>::new();
+
+		for partition in &self.partitions {
+			let time_tail = partition.get_time_tail();
+			let time_head = partition.get_time_head();
+
+			if time_head < from || time_tail >= until {
+				partitions.push(partition.clone());
+				continue;
+			}
+
+			if time_tail >= from && time_head < until {
+				partitions_deleted.push(partition.clone());
+				continue;
+			}
+
+			let measurements = partition.read_measurements()?;
+			let measurements_kept = measurements
+				.iter()
+				.filter(|m| m.time < from || m.time >= until)
+				.collect::<Vec<_>>();
+
+			if measurements_kept.len() == measurements.len() {
+				partitions.push(partition.clone());
+				continue;
+			}
+
+			partitions_deleted.push(partition.clone());
+
+			if measurements_kept.is_empty() {
+				continue;
+			}
+
+			let mut partition_new =
+				LogfilePartition::create(&self.backend, &self.path, self.next_sequence, time_tail)?;
+
+			self.next_sequence += 1;
+			self.directory_dirty = true;
+
+			for measurement in measurements_kept {
+				partition_new.append_measurement(measurement)?;
+			}
+
+			partitions.push(partition_new);
+		}
+
+		// the late partition is not sorted by time, so it is rewritten whenever it
+		// overlaps the range
+		let mut late_partition = self.late_partition.clone();
+		let late_partition_overlapping = self
+			.late_partition
+			.clone()
+			.filter(|p| p.get_time_head() >= from && p.get_time_tail() < until);
+
+		if let Some(partition) = late_partition_overlapping {
+			let measurements = partition.read_measurements()?;
+			let measurements_kept = measurements
+				.iter()
+				.filter(|m| m.time < from || m.time >= until)
+				.collect::<Vec<_>>();
+
+			if measurements_kept.len() != measurements.len() {
+				partitions_deleted.push(partition);
+				late_partition = None;
+			}
+
+			if measurements_kept.len() != measurements.len() && !measurements_kept.is_empty() {
+				let mut partition_new = LogfilePartition::create_late(
+					&self.backend,
+					&self.path,
+					self.next_sequence,
+					measurements_kept[0].time,
+				)?;
+
+				self.next_sequence += 1;
+				self.directory_dirty = true;
+
+				for measurement in measurements_kept {
+					partition_new.append_late_measurement(measurement)?;
+				}
+
+				late_partition = Some(partition_new);
+			}
+		}
+
+		self.partitions = partitions;
+		self.late_partition = late_partition;
+		self.partitions_deleted.append(&mut partitions_deleted);
+		Ok(())
+	}
+
 	pub fn clear(&mut self) -> Result<(), ::Error> {
 		self.partitions_deleted.append(&mut self.partitions);
 		self.partitions.clear();
+
+		if let Some(late_partition) = self.late_partition.take() {
+			self.partitions_deleted.push(late_partition);
+		}
+
+		Ok(())
+	}
+
+	// drops the partitions whose newest measurement is older than the max age
+	pub fn expire(&mut self) -> Result<(), ::Error> {
+		let max_age = match self.max_age {
+			Some(v) => v,
+			None => return Ok(()),
+		};
+
+		let max_age_micros = max_age.as_secs() * 1_000_000 + max_age.subsec_micros() as u64;
+		let cutoff = self
+			.clock
+			.get_unix_microseconds()?
+			.saturating_sub(max_age_micros);
+
+		while self
+			.partitions
+			.first()
+			.is_some_and(|p| p.get_time_head() < cutoff)
+		{
+			let deleted_partition = self.partitions.remove(0);
+			self.partitions_deleted.push(deleted_partition);
+		}
+
+		let is_late_partition_expired = match self.late_partition {
+			Some(ref p) => p.get_time_head() < cutoff,
+			None => false,
+		};
+
+		if is_late_partition_expired {
+			if let Some(deleted_partition) = self.late_partition.take() {
+				self.partitions_deleted.push(deleted_partition);
+			}
+		}
+
 		Ok(())
 	}
 
+	// drops partitions until the max age and the storage quota are met, so that
+	// the stricter of the two limits applies
 	pub fn garbage_collect(&mut self, new_bytes: u64) -> Result<(), ::Error> {
+		self.expire()?;
+
+		let late_bytes = match self.late_partition {
+			Some(ref p) => p.get_file_offset(),
+			None => 0,
+		};
+
 		let mut required_bytes: u64 = new_bytes
+			+ late_bytes
 			+ self
 				.partitions
 				.iter()
 				.fold(0, |s, x| s + x.get_file_offset());
 
 		while !self.storage_quota.is_sufficient_bytes(required_bytes) {
-			if self.partitions.is_empty() {
+			if self.partitions.is_empty() && self.late_partition.is_none() {
 				return Err(err_server!("corrupt partition map"));
 			}
 
-			let deleted_partition = self.partitions.remove(0);
-			required_bytes -= deleted_partition.get_file_offset();
-			self.partitions_deleted.push(deleted_partition);
+			required_bytes -= self.evict_partition();
 		}
 
 		Ok(())
 	}
+
+	// drops the oldest partition without committing the transaction and returns
+	// the number of bytes freed. the partition files are deleted by the next
+	// commit
+	pub fn evict_partition(&mut self) -> u64 {
+		if self.partitions.is_empty() {
+			return match self.late_partition.take() {
+				Some(deleted_partition) => {
+					let freed_bytes = deleted_partition.get_file_offset();
+					self.partitions_deleted.push(deleted_partition);
+					freed_bytes
+				}
+				None => 0,
+			};
+		}
+
+		let deleted_partition = self.partitions.remove(0);
+		let mut freed_bytes = deleted_partition.get_file_offset();
+		self.partitions_deleted.push(deleted_partition);
+
+		// drop the late partition once all of its measurements are older
+		// than the remaining measurements
+		let is_late_partition_expired = match (&self.late_partition, self.partitions.first()) {
+			(Some(late), Some(tail)) => late.get_time_head() < tail.get_time_tail(),
+			_ => false,
+		};
+
+		if is_late_partition_expired {
+			if let Some(deleted_partition) = self.late_partition.take() {
+				freed_bytes += deleted_partition.get_file_offset();
+				self.partitions_deleted.push(deleted_partition);
+			}
+		}
+
+		freed_bytes
+	}
+
+	pub fn get_usage(&self) -> LogfileUsage {
+		let late_bytes = match self.late_partition {
+			Some(ref p) => p.get_file_offset(),
+			None => 0,
+		};
+
+		// the partition that is evicted next
+		let oldest_partition = match self.partitions.first() {
+			Some(p) => Some(p),
+			None => self.late_partition.as_ref(),
+		};
+
+		LogfileUsage {
+			id: self.id.clone(),
+			bytes: self
+				.partitions
+				.iter()
+				.fold(late_bytes, |s, x| s + x.get_file_offset()),
+			partitions: self.partitions.len() as u64 + self.late_partition.is_some() as u64,
+			oldest_partition_time: oldest_partition.map(|p| p.get_time_head()),
+		}
+	}
+}
+
+pub fn get_late_partition_sequence(
+	partition: &LogfileTransactionPartition,
+) -> Result<u64, ::Error> {
+	match partition.sequence {
+		Some(sequence) => Ok(sequence),
+		None => Err(err_corrupt!("late partition without a sequence number")),
+	}
+}
+
+fn has_partition_files(backend: &dyn StorageBackend, path: &Path) -> Result<bool, ::Error> {
+	if !backend.is_dir(path) {
+		return Ok(false);
+	}
+
+	for file_name in backend.list(path)? {
+		if logfile_partition::is_partition_file_name(&file_name) {
+			return Ok(true);
+		}
+	}
+
+	Ok(false)
 }
@@ -18,17 +18,59 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
+use clock_watchdog::ClockWatchdogConfig;
+use durability::Durability;
+use eviction::{EvictionPolicy, OldestFirstPolicy};
 use logfile_id::LogfileID;
 use quota::StorageQuota;
+use quota_rule::{self, QuotaExplanation, QuotaGroup, QuotaRule, QuotaSource};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
 const DEFAULT_PARTITION_SIZE_MAX_BYTES: u64 = 1024 * 128;
+const DEFAULT_RETENTION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// What to do with a measurement that is older than the newest stored measurement
+// of the same sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfOrderPolicy {
+	// delete all stored measurements of the sensor and store the new measurement
+	Flush,
+	// reject the new measurement with an error
+	Reject,
+	// store the new measurement with the time of the newest stored measurement
+	Clamp,
+	// store the new measurement in a separate late partition that is merged with
+	// the other measurements on read. the late partition is limited to the
+	// partition size
+	LateBuffer,
+}
 
 #[derive(Debug, Clone)]
 pub struct LogfileConfig {
 	quota_default: StorageQuota,
 	quota: HashMap<LogfileID, StorageQuota>,
+	// quotas for all sensors whose id matches a pattern. the quota of a single
+	// sensor takes precedence over the rules
+	quota_rules: Vec<QuotaRule>,
+	// quotas that are shared by all sensors of a group. the quota of each sensor
+	// still applies within the group
+	quota_groups: Vec<QuotaGroup>,
+	// the total storage of all logfiles in the data directory. once it is
+	// exceeded, partitions are evicted as decided by the eviction policy
+	storage_budget: StorageQuota,
+	eviction_policy: Arc<dyn EvictionPolicy>,
+	// measurements older than the max age are dropped, regardless of the quota.
+	// None keeps measurements until the quota is used up
+	max_age_default: Option<Duration>,
+	max_age: HashMap<LogfileID, Option<Duration>>,
+	retention_sweep_interval: Duration,
+	out_of_order_policy_default: OutOfOrderPolicy,
+	out_of_order_policy: HashMap<LogfileID, OutOfOrderPolicy>,
 	partition_size_bytes_default: u64,
+	clock_watchdog: Option<ClockWatchdogConfig>,
+	durability: Durability,
 }
 
 impl Default for LogfileConfig {
@@ -42,25 +84,145 @@ impl LogfileConfig {
 		LogfileConfig {
 			quota_default: StorageQuota::Zero,
 			quota: HashMap::<LogfileID, StorageQuota>::new(),
+			quota_rules: Vec::<QuotaRule>::new(),
+			quota_groups: Vec::<QuotaGroup>::new(),
+			storage_budget: StorageQuota::Unlimited,
+			eviction_policy: Arc::new(OldestFirstPolicy::new()),
+			max_age_default: None,
+			max_age: HashMap::<LogfileID, Option<Duration>>::new(),
+			retention_sweep_interval: DEFAULT_RETENTION_SWEEP_INTERVAL,
+			out_of_order_policy_default: OutOfOrderPolicy::Flush,
+			out_of_order_policy: HashMap::<LogfileID, OutOfOrderPolicy>::new(),
 			partition_size_bytes_default: DEFAULT_PARTITION_SIZE_MAX_BYTES,
+			clock_watchdog: None,
+			durability: Durability::Always,
 		}
 	}
 
 	pub fn get_storage_quota_for(&self, logfile_id: &LogfileID) -> StorageQuota {
-		self.quota
-			.get(logfile_id)
-			.unwrap_or(&self.quota_default)
-			.clone()
+		self.explain_storage_quota_for(logfile_id).quota
+	}
+
+	// returns the storage quota of the sensor and where it comes from
+	pub fn explain_storage_quota_for(&self, logfile_id: &LogfileID) -> QuotaExplanation {
+		if let Some(quota) = self.quota.get(logfile_id) {
+			return QuotaExplanation {
+				quota: quota.clone(),
+				source: QuotaSource::Sensor,
+			};
+		}
+
+		if let Some((index, rule)) = quota_rule::find_rule(&self.quota_rules, logfile_id) {
+			return QuotaExplanation {
+				quota: rule.quota.clone(),
+				source: QuotaSource::Rule {
+					index,
+					rule: rule.clone(),
+				},
+			};
+		}
+
+		QuotaExplanation {
+			quota: self.quota_default.clone(),
+			source: QuotaSource::Default,
+		}
 	}
 
 	pub fn set_storage_quota_for(&mut self, logfile_id: &LogfileID, quota: StorageQuota) {
 		self.quota.insert(logfile_id.clone(), quota);
 	}
 
+	// drops the quota that was set for exactly this sensor, so that the quota
+	// rules and the default apply again
+	pub fn remove_storage_quota_for(&mut self, logfile_id: &LogfileID) {
+		self.quota.remove(logfile_id);
+	}
+
 	pub fn set_default_storage_quota(&mut self, quota: StorageQuota) {
 		self.quota_default = quota;
 	}
 
+	// adds a quota rule. if several rules match a sensor id, the rule with the
+	// most literal characters in its pattern wins, see quota_rule::find_rule
+	pub fn add_storage_quota_rule(&mut self, rule: QuotaRule) {
+		self.quota_rules.push(rule);
+	}
+
+	pub fn get_storage_quota_rules(&self) -> &[QuotaRule] {
+		&self.quota_rules
+	}
+
+	pub fn add_quota_group(&mut self, group: QuotaGroup) {
+		self.quota_groups.push(group);
+	}
+
+	pub fn get_quota_groups(&self) -> &[QuotaGroup] {
+		&self.quota_groups
+	}
+
+	pub fn get_storage_budget(&self) -> StorageQuota {
+		self.storage_budget.clone()
+	}
+
+	pub fn set_storage_budget(&mut self, budget: StorageQuota) {
+		self.storage_budget = budget;
+	}
+
+	pub fn get_eviction_policy(&self) -> Arc<dyn EvictionPolicy> {
+		self.eviction_policy.clone()
+	}
+
+	pub fn set_eviction_policy(&mut self, policy: Arc<dyn EvictionPolicy>) {
+		self.eviction_policy = policy;
+	}
+
+	pub fn get_max_age_for(&self, logfile_id: &LogfileID) -> Option<Duration> {
+		*self
+			.max_age
+			.get(logfile_id)
+			.unwrap_or(&self.max_age_default)
+	}
+
+	pub fn set_max_age_for(&mut self, logfile_id: &LogfileID, max_age: Option<Duration>) {
+		self.max_age.insert(logfile_id.clone(), max_age);
+	}
+
+	pub fn set_default_max_age(&mut self, max_age: Option<Duration>) {
+		self.max_age_default = max_age;
+	}
+
+	// returns true if a max age is set for any sensor
+	pub fn has_max_age(&self) -> bool {
+		self.max_age_default.is_some() || self.max_age.values().any(|v| v.is_some())
+	}
+
+	pub fn get_retention_sweep_interval(&self) -> Duration {
+		self.retention_sweep_interval
+	}
+
+	pub fn set_retention_sweep_interval(&mut self, interval: Duration) {
+		self.retention_sweep_interval = interval;
+	}
+
+	pub fn get_out_of_order_policy_for(&self, logfile_id: &LogfileID) -> OutOfOrderPolicy {
+		*self
+			.out_of_order_policy
+			.get(logfile_id)
+			.unwrap_or(&self.out_of_order_policy_default)
+	}
+
+	pub fn set_out_of_order_policy_for(
+		&mut self,
+		logfile_id: &LogfileID,
+		policy: OutOfOrderPolicy,
+	) {
+		self.out_of_order_policy.insert(logfile_id.clone(), policy);
+	}
+
+	pub fn set_default_out_of_order_policy(&mut self, policy: OutOfOrderPolicy) {
+		self.out_of_order_policy_default = policy;
+	}
+
 	pub fn get_partition_size_for(&self, _logfile_id: &LogfileID) -> u64 {
 		self.partition_size_bytes_default
 	}
@@ -68,4 +230,20 @@ impl LogfileConfig {
 	pub fn set_default_partition_size_bytes(&mut self, limit: u64) {
 		self.partition_size_bytes_default = limit;
 	}
+
+	pub fn get_clock_watchdog(&self) -> Option<ClockWatchdogConfig> {
+		self.clock_watchdog.clone()
+	}
+
+	pub fn set_clock_watchdog(&mut self, config: Option<ClockWatchdogConfig>) {
+		self.clock_watchdog = config;
+	}
+
+	pub fn get_durability(&self) -> Durability {
+		self.durability
+	}
+
+	pub fn set_durability(&mut self, durability: Durability) {
+		self.durability = durability;
+	}
 }
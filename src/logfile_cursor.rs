@@ -0,0 +1,542 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use fetch::{FetchDirection, FetchOptions};
+use logfile_partition::LogfilePartition;
+use measure::Measurement;
+use std::cmp::Reverse;
+use storage_backend::StorageFile;
+
+// A cursor that lazily yields the measurements of a logfile in the requested
+// direction. The cursor operates on a snapshot of the partition list and does not
+// hold the logfile lock, so writers are not blocked while the cursor is consumed.
+//
+// Records can only be decoded back-to-front. To scan a partition in ascending
+// order, the cursor first walks the record footers backwards and builds an index
+// of the record boundaries in the partition.
+//
+// Measurements from the late partition (see OutOfOrderPolicy::LateBuffer) are
+// loaded into memory and sorted when the cursor is opened and then merged with
+// the measurements from the regular partitions.
+#[derive(Debug)]
+pub struct MeasurementCursor {
+	partitions: Vec<LogfilePartition>,
+	late_partition: Option<LogfilePartition>,
+	time_start: Option<u64>,
+	time_limit: Option<u64>,
+	direction: FetchDirection,
+	partition_index: usize,
+	partition_offset: u64,
+	partition_file: Option<Box<dyn StorageFile>>,
+	partition_records: Vec<u64>,
+	pending: Option<Measurement>,
+	pending_position: (usize, u64),
+	late_records: Vec<LateRecord>,
+	done: bool,
+}
+
+#[derive(Debug)]
+struct LateRecord {
+	time: u64,
+	offset: u64,
+	measurement: Measurement,
+}
+
+impl MeasurementCursor {
+	fn new(
+		partitions: Vec<LogfilePartition>,
+		late_partition: Option<LogfilePartition>,
+		options: &FetchOptions,
+	) -> MeasurementCursor {
+		let (partition_index, partition_offset) = match (options.direction, partitions.last()) {
+			(FetchDirection::Descending, Some(p)) => (partitions.len() - 1, p.get_file_offset()),
+			_ => (0, 0),
+		};
+
+		MeasurementCursor {
+			done: partitions.is_empty(),
+			partitions,
+			late_partition,
+			time_start: options.time_start,
+			time_limit: options.time_limit,
+			direction: options.direction,
+			partition_index,
+			partition_offset,
+			partition_file: None,
+			partition_records: Vec::new(),
+			pending: None,
+			pending_position: (0, 0),
+			late_records: Vec::new(),
+		}
+	}
+
+	// creates a new cursor for the given fetch options and moves it to the position
+	// of the continuation token if one is given
+	pub fn open(
+		partitions: Vec<LogfilePartition>,
+		late_partition: Option<LogfilePartition>,
+		options: &FetchOptions,
+	) -> Result<MeasurementCursor, ::Error> {
+		let mut cursor = MeasurementCursor::new(partitions, late_partition, options);
+		cursor.load_late_records()?;
+
+		if let Some(ref continuation) = options.continuation {
+			cursor.seek(continuation)?;
+		}
+
+		Ok(cursor)
+	}
+
+	// moves the cursor to a position previously returned by get_position
+	pub fn seek(&mut self, position: &str) -> Result<(), ::Error> {
+		let (direction, main_position, late_position) = match parse_position(position) {
+			Some(v) => v,
+			None => return Err(err_user!("invalid cursor position: {}", position)),
+		};
+
+		if direction != self.direction {
+			return Err(err_user!(
+				"cursor position {} does not match the fetch direction",
+				position
+			));
+		}
+
+		self.pending = None;
+		self.partition_file = None;
+
+		match main_position {
+			Some((partition_key, offset)) => {
+				let partition_index = match self
+					.partitions
+					.iter()
+					.rposition(|p| get_position_key(p) == partition_key)
+				{
+					Some(i) => i,
+					None => {
+						return Err(err_user!(
+							"cursor position {} refers to a partition that no longer exists; \
+							 the data was probably garbage collected",
+							position
+						))
+					}
+				};
+
+				if offset > self.partitions[partition_index].get_file_offset() {
+					return Err(err_user!("invalid cursor position: {}", position));
+				}
+
+				self.partition_index = partition_index;
+				self.partition_offset = offset;
+				self.done = false;
+			}
+			None => {
+				self.done = true;
+			}
+		}
+
+		self.load_late_records()?;
+		match late_position {
+			Some(late_position) => {
+				let direction = self.direction;
+				self.late_records.retain(|r| match direction {
+					FetchDirection::Descending => (r.time, r.offset) <= late_position,
+					FetchDirection::Ascending => (r.time, r.offset) >= late_position,
+				});
+			}
+			None => self.late_records.clear(),
+		}
+
+		Ok(())
+	}
+
+	// returns an opaque token that identifies the position of the next measurement
+	// or None if the cursor is exhausted
+	pub fn get_position(&self) -> Option<String> {
+		let main_position = match self.pending {
+			Some(_) => Some(self.pending_position),
+			None if !self.done => Some((self.partition_index, self.partition_offset)),
+			None => None,
+		};
+
+		let late_position = self.late_records.last().map(|r| (r.time, r.offset));
+
+		if main_position.is_none() && late_position.is_none() {
+			return None;
+		}
+
+		let direction = match self.direction {
+			FetchDirection::Descending => "d",
+			FetchDirection::Ascending => "a",
+		};
+
+		let main_position = match main_position {
+			Some((index, offset)) => {
+				format!("{}.{}", get_position_key(&self.partitions[index]), offset)
+			}
+			None => "x".to_string(),
+		};
+
+		let late_position = match late_position {
+			Some((time, offset)) => format!("{}.{}", time, offset),
+			None => "x".to_string(),
+		};
+
+		Some(format!("{}:{}:{}", direction, main_position, late_position))
+	}
+
+	fn next_measurement(&mut self) -> Result<Option<Measurement>, ::Error> {
+		if self.pending.is_none() && !self.done {
+			let position = (self.partition_index, self.partition_offset);
+			self.pending = self.next_partition_measurement()?;
+			self.pending_position = position;
+		}
+
+		// merge the measurements from the late partition with the measurements
+		// from the regular partitions
+		let is_late_next = match (&self.pending, self.late_records.last()) {
+			(_, None) => false,
+			(None, Some(_)) => true,
+			(Some(pending), Some(late)) => match self.direction {
+				FetchDirection::Descending => late.time > pending.time,
+				FetchDirection::Ascending => late.time < pending.time,
+			},
+		};
+
+		if is_late_next {
+			Ok(self.late_records.pop().map(|r| r.measurement))
+		} else {
+			Ok(self.pending.take())
+		}
+	}
+
+	// loads all measurements in the time range from the late partition. the
+	// records are sorted in reverse order so that the next record can be popped
+	// from the end
+	fn load_late_records(&mut self) -> Result<(), ::Error> {
+		self.late_records.clear();
+
+		let partition = match self.late_partition {
+			Some(ref p) => p,
+			None => return Ok(()),
+		};
+
+		if partition.get_file_offset() == 0 {
+			return Ok(());
+		}
+
+		let mut file = open_partition_file(partition)?;
+		let mut offset = partition.get_file_offset();
+		while offset > 0 {
+			let (measurement, measurement_size) =
+				match Measurement::decode(&mut file, offset, partition.get_record_format()) {
+					Ok(v) => v,
+					Err(e) => return Err(partition.annotate_error(e, offset)),
+				};
+
+			let is_in_range = match (self.time_limit, self.time_start) {
+				(Some(time_limit), _) if measurement.time <= time_limit => false,
+				(_, Some(time_start)) if measurement.time > time_start => false,
+				_ => true,
+			};
+
+			if is_in_range {
+				self.late_records.push(LateRecord {
+					time: measurement.time,
+					offset,
+					measurement,
+				});
+			}
+
+			offset -= measurement_size;
+		}
+
+		match self.direction {
+			FetchDirection::Descending => {
+				self.late_records.sort_by_key(|r| (r.time, r.offset));
+			}
+			FetchDirection::Ascending => {
+				self.late_records
+					.sort_by_key(|r| Reverse((r.time, r.offset)));
+			}
+		}
+
+		Ok(())
+	}
+
+	fn next_partition_measurement(&mut self) -> Result<Option<Measurement>, ::Error> {
+		loop {
+			if self.done {
+				return Ok(None);
+			}
+
+			if self.partition_file.is_none() {
+				if !self.is_partition_in_range() {
+					continue;
+				}
+
+				// empty partitions may not have a file yet
+				if self.partitions[self.partition_index].get_file_offset() == 0 {
+					self.seek_next_partition();
+					continue;
+				}
+
+				self.open_partition()?;
+			}
+
+			// find the end offset of the next record in the partition
+			let record_end = match self.direction {
+				FetchDirection::Descending => match self.partition_offset {
+					0 => None,
+					offset => Some(offset),
+				},
+				FetchDirection::Ascending => {
+					let next_index =
+						match self.partition_records.binary_search(&self.partition_offset) {
+							Ok(i) => i + 1,
+							Err(i) => i,
+						};
+
+					self.partition_records.get(next_index).cloned()
+				}
+			};
+
+			let record_end = match record_end {
+				Some(v) => v,
+				None => {
+					self.seek_next_partition();
+					continue;
+				}
+			};
+
+			let partition = &self.partitions[self.partition_index];
+			let (measurement, measurement_size) = match self.partition_file {
+				Some(ref mut file) => {
+					match Measurement::decode(file, record_end, partition.get_record_format()) {
+						Ok(v) => v,
+						Err(e) => return Err(partition.annotate_error(e, record_end)),
+					}
+				}
+				None => return Err(err_server!("partition is not open")),
+			};
+
+			self.partition_offset = match self.direction {
+				FetchDirection::Descending => record_end - measurement_size,
+				FetchDirection::Ascending => record_end,
+			};
+
+			// skip measurements that are not part of the time range and stop
+			// once the end of the time window is reached
+			match self.direction {
+				FetchDirection::Descending => {
+					if let Some(time_start) = self.time_start {
+						if measurement.time > time_start {
+							continue;
+						}
+					}
+
+					if let Some(time_limit) = self.time_limit {
+						if measurement.time <= time_limit {
+							self.done = true;
+							continue;
+						}
+					}
+				}
+				FetchDirection::Ascending => {
+					if let Some(time_limit) = self.time_limit {
+						if measurement.time <= time_limit {
+							continue;
+						}
+					}
+
+					if let Some(time_start) = self.time_start {
+						if measurement.time > time_start {
+							self.done = true;
+							continue;
+						}
+					}
+				}
+			}
+
+			return Ok(Some(measurement));
+		}
+	}
+
+	// checks if the current partition can contain measurements in the time range.
+	// skips the partition or stops the scan if it can not
+	fn is_partition_in_range(&mut self) -> bool {
+		let (time_head, time_tail) = {
+			let partition = &self.partitions[self.partition_index];
+			(partition.get_time_head(), partition.get_time_tail())
+		};
+
+		let is_before_range = match self.time_limit {
+			Some(time_limit) => time_head <= time_limit,
+			None => false,
+		};
+
+		let is_after_range = match self.time_start {
+			Some(time_start) => time_tail > time_start,
+			None => false,
+		};
+
+		match self.direction {
+			FetchDirection::Descending if is_after_range => self.seek_next_partition(),
+			FetchDirection::Descending if is_before_range => self.done = true,
+			FetchDirection::Ascending if is_before_range => self.seek_next_partition(),
+			FetchDirection::Ascending if is_after_range => self.done = true,
+			_ => return true,
+		};
+
+		false
+	}
+
+	fn open_partition(&mut self) -> Result<(), ::Error> {
+		let partition = &self.partitions[self.partition_index];
+
+		let mut file = open_partition_file(partition)?;
+
+		// build the index of record boundaries for ascending scans
+		self.partition_records.clear();
+		if self.direction == FetchDirection::Ascending {
+			let mut offset = partition.get_file_offset();
+			while offset > 0 {
+				self.partition_records.push(offset);
+				offset -= match Measurement::decode_size(
+					&mut file,
+					offset,
+					partition.get_record_format(),
+				) {
+					Ok(v) => v,
+					Err(e) => return Err(partition.annotate_error(e, offset)),
+				};
+			}
+
+			self.partition_records.reverse();
+
+			let is_record_boundary = self.partition_offset == 0
+				|| self.partition_records.contains(&self.partition_offset);
+
+			if !is_record_boundary {
+				return Err(err_user!(
+					"invalid cursor position: offset {} in partition {:?}",
+					self.partition_offset,
+					partition.get_file_path()
+				));
+			}
+		}
+
+		self.partition_file = Some(file);
+		Ok(())
+	}
+
+	fn seek_next_partition(&mut self) {
+		self.partition_file = None;
+
+		match self.direction {
+			FetchDirection::Descending => {
+				if self.partition_index == 0 {
+					self.done = true;
+					return;
+				}
+
+				self.partition_index -= 1;
+				self.partition_offset = self.partitions[self.partition_index].get_file_offset();
+			}
+			FetchDirection::Ascending => {
+				if self.partition_index + 1 == self.partitions.len() {
+					self.done = true;
+					return;
+				}
+
+				self.partition_index += 1;
+				self.partition_offset = 0;
+			}
+		}
+	}
+}
+
+impl Iterator for MeasurementCursor {
+	type Item = Result<Measurement, ::Error>;
+
+	fn next(&mut self) -> Option<Result<Measurement, ::Error>> {
+		match self.next_measurement() {
+			Ok(Some(measurement)) => Some(Ok(measurement)),
+			Ok(None) => None,
+			Err(e) => {
+				self.done = true;
+				self.pending = None;
+				self.late_records.clear();
+				Some(Err(e))
+			}
+		}
+	}
+}
+
+fn open_partition_file(partition: &LogfilePartition) -> Result<Box<dyn StorageFile>, ::Error> {
+	match partition.open_file()? {
+		Some(f) => Ok(f),
+		None => Err(err_server!(
+			"partition {:?} was deleted while reading",
+			partition.get_file_path()
+		)),
+	}
+}
+
+type Position = (FetchDirection, Option<(String, u64)>, Option<(u64, u64)>);
+
+// partitions are identified by their sequence number in positions. legacy
+// partitions without a sequence number are identified by their time tail
+fn get_position_key(partition: &LogfilePartition) -> String {
+	match partition.get_sequence() {
+		Some(sequence) => format!("p{}", sequence),
+		None => partition.get_time_tail().to_string(),
+	}
+}
+
+fn parse_position(position: &str) -> Option<Position> {
+	let mut parts = position.splitn(3, ':');
+
+	let direction = match parts.next()? {
+		"d" => FetchDirection::Descending,
+		"a" => FetchDirection::Ascending,
+		_ => return None,
+	};
+
+	let main_position = match parse_position_pair(parts.next()?)? {
+		Some((key, offset)) => Some((key.to_string(), offset.parse::<u64>().ok()?)),
+		None => None,
+	};
+
+	let late_position = match parse_position_pair(parts.next()?)? {
+		Some((time, offset)) => Some((time.parse::<u64>().ok()?, offset.parse::<u64>().ok()?)),
+		None => None,
+	};
+
+	Some((direction, main_position, late_position))
+}
+
+// parses a "<a>.<b>" pair or "x" for an exhausted position
+fn parse_position_pair(pair: &str) -> Option<Option<(&str, &str)>> {
+	if pair == "x" {
+		return Some(None);
+	}
+
+	Some(Some(pair.split_once('.')?))
+}
@@ -18,42 +18,112 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
+use datadir_lock::DatadirLock;
 use logfile::Logfile;
 use logfile_config::LogfileConfig;
 use logfile_id::{LogfileID, LogfilePath};
-use std::fs;
+use logfile_recovery::RecoveryReport;
+use quota::StorageQuota;
+use serde_json as json;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use storage_backend::StorageBackend;
+use time::Clock;
 
 const DATABASE_PATH: &str = "db";
 
+// logfile directories are renamed before they are deleted, see delete_logfile
+const DELETED_LOGFILE_SUFFIX: &str = ".deleted";
+
+const QUOTA_OVERRIDES_FILE_NAME: &str = "quota_overrides";
+
 #[derive(Debug, Clone)]
 pub struct LogfileDirectory {
 	pub path: PathBuf,
+	backend: Arc<dyn StorageBackend>,
+	// the lock is held for as long as the directory is open. read-only
+	// directories and directories on volatile backends do not hold the lock
+	#[allow(dead_code)]
+	lock: Option<Arc<DatadirLock>>,
+	read_only: bool,
+}
+
+// The storage quotas that were changed at runtime, by sensor id
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct QuotaOverridesFile {
+	quota: BTreeMap<String, StorageQuota>,
 }
 
 impl LogfileDirectory {
-	pub fn open(path: &Path) -> Result<LogfileDirectory, ::Error> {
-		fs::create_dir_all(path.join(DATABASE_PATH))?;
+	pub fn open(
+		backend: Arc<dyn StorageBackend>,
+		path: &Path,
+	) -> Result<LogfileDirectory, ::Error> {
+		let lock = if backend.is_volatile() {
+			None
+		} else {
+			Some(Arc::new(DatadirLock::acquire(path)?))
+		};
+
+		::fs_util::create_dir_all_synced(&*backend, &path.join(DATABASE_PATH))?;
 
 		let logfile_directory = LogfileDirectory {
 			path: path.to_owned(),
+			backend,
+			lock,
+			read_only: false,
 		};
 
 		Ok(logfile_directory)
 	}
 
+	// opens the directory without taking the lock and without creating anything,
+	// so that it can be inspected while another process is writing to it
+	pub fn open_read_only(
+		backend: Arc<dyn StorageBackend>,
+		path: &Path,
+	) -> Result<LogfileDirectory, ::Error> {
+		if !backend.is_dir(&path.join(DATABASE_PATH)) {
+			return Err(err_user!("not a sensorlog data directory: {:?}", path));
+		}
+
+		let logfile_directory = LogfileDirectory {
+			path: path.to_owned(),
+			backend,
+			lock: None,
+			read_only: true,
+		};
+
+		Ok(logfile_directory)
+	}
+
+	pub fn is_read_only(&self) -> bool {
+		self.read_only
+	}
+
+	pub fn get_backend(&self) -> Arc<dyn StorageBackend> {
+		self.backend.clone()
+	}
+
 	pub fn create_logfile(
 		&self,
 		logfile_id: &LogfileID,
 		logfile_config: &LogfileConfig,
+		clock: &Arc<dyn Clock>,
 	) -> Result<Arc<Logfile>, ::Error> {
 		let logfile_path = self
 			.path
 			.join(DATABASE_PATH)
 			.join(logfile_id.get_path().get_file_name());
 
-		let logfile = Logfile::create(logfile_id.clone(), &logfile_path, logfile_config)?;
+		let logfile = Logfile::create(
+			&self.backend,
+			clock,
+			logfile_id.clone(),
+			&logfile_path,
+			logfile_config,
+		)?;
 
 		Ok(Arc::new(logfile))
 	}
@@ -62,25 +132,159 @@ impl LogfileDirectory {
 		&self,
 		logfile_path: &LogfilePath,
 		logfile_config: &LogfileConfig,
+		clock: &Arc<dyn Clock>,
 	) -> Result<Option<Arc<Logfile>>, ::Error> {
 		let logfile_path = self
 			.path
 			.join(DATABASE_PATH)
 			.join(&logfile_path.get_file_name());
 
-		let logfile = Logfile::open(&logfile_path, logfile_config)?;
+		let logfile = Logfile::open(&self.backend, clock, &logfile_path, logfile_config)?;
 
 		Ok(logfile.map(Arc::new))
 	}
 
+	// deletes the leftover swap files of a logfile that was never committed
+	pub fn recover_uncommitted_logfile(
+		&self,
+		logfile_path: &LogfilePath,
+	) -> Result<RecoveryReport, ::Error> {
+		let logfile_path = self
+			.path
+			.join(DATABASE_PATH)
+			.join(logfile_path.get_file_name());
+
+		::logfile_recovery::recover(&*self.backend, &logfile_path, &mut [])
+	}
+
+	// deletes the logfile directory. the directory is renamed first, which is the
+	// commit point of the deletion: after a crash, the logfile is either still
+	// present or the renamed directory is purged when the directory is opened.
+	// returns false if the logfile does not exist
+	pub fn delete_logfile(&self, logfile_path: &LogfilePath) -> Result<bool, ::Error> {
+		let path = self.get_logfile_path(logfile_path);
+		if !self.backend.exists(&path) {
+			return Ok(false);
+		}
+
+		info!("Deleting logfile; path={:?}", path);
+
+		// leftovers of an earlier deletion that were not purged yet
+		let path_deleted = self.get_logfile_path_deleted(logfile_path);
+		if self.backend.exists(&path_deleted) {
+			self.purge_logfile(&path_deleted)?;
+		}
+
+		self.backend.rename(&path, &path_deleted)?;
+		::fs_util::sync_parent_dir(&*self.backend, &path)?;
+
+		if let Err(e) = self.purge_logfile(&path_deleted) {
+			warn!(
+				"Error while purging deleted logfile; path={:?}: {}; retrying on next open",
+				path_deleted, e
+			);
+		}
+
+		Ok(true)
+	}
+
+	// deletes the directories of logfiles whose deletion was interrupted
+	pub fn purge_deleted_logfiles(&self) -> Result<RecoveryReport, ::Error> {
+		let mut report = RecoveryReport::default();
+
+		for file_name in self.backend.list(&self.path.join(DATABASE_PATH))? {
+			if !file_name.ends_with(DELETED_LOGFILE_SUFFIX) {
+				continue;
+			}
+
+			let path = self.path.join(DATABASE_PATH).join(&file_name);
+			warn!("Purging deleted logfile; path={:?}", path);
+			report.files_deleted += self.purge_logfile(&path)?;
+		}
+
+		Ok(report)
+	}
+
+	fn purge_logfile(&self, path: &Path) -> Result<u64, ::Error> {
+		let mut files_deleted = 0;
+		for file_name in self.backend.list(path)? {
+			self.backend.remove(&path.join(file_name))?;
+			files_deleted += 1;
+		}
+
+		self.backend.remove_dir(path)?;
+		::fs_util::sync_parent_dir(&*self.backend, path)?;
+
+		Ok(files_deleted)
+	}
+
+	pub fn read_quota_overrides(&self) -> Result<BTreeMap<String, StorageQuota>, ::Error> {
+		let data = match self
+			.backend
+			.read(&self.path.join(QUOTA_OVERRIDES_FILE_NAME))?
+		{
+			Some(data) => data,
+			None => return Ok(BTreeMap::new()),
+		};
+
+		match json::from_slice::<QuotaOverridesFile>(&data) {
+			Ok(v) => Ok(v.quota),
+			Err(e) => Err(err_server!(
+				"error while decoding quota overrides file: {}",
+				e
+			)),
+		}
+	}
+
+	pub fn write_quota_overrides(
+		&self,
+		quota: &BTreeMap<String, StorageQuota>,
+	) -> Result<(), ::Error> {
+		let file = QuotaOverridesFile {
+			quota: quota.clone(),
+		};
+
+		let encoded = match json::to_vec(&file) {
+			Ok(v) => v,
+			Err(e) => {
+				return Err(err_server!(
+					"error while encoding quota overrides file: {}",
+					e
+				))
+			}
+		};
+
+		::fs_util::write_file_atomic(
+			&*self.backend,
+			&self.path.join(QUOTA_OVERRIDES_FILE_NAME),
+			&encoded,
+			true,
+		)
+	}
+
+	pub fn get_logfile_path(&self, logfile_path: &LogfilePath) -> PathBuf {
+		self.path
+			.join(DATABASE_PATH)
+			.join(logfile_path.get_file_name())
+	}
+
+	fn get_logfile_path_deleted(&self, logfile_path: &LogfilePath) -> PathBuf {
+		self.path.join(DATABASE_PATH).join(format!(
+			"{}{}",
+			logfile_path.get_file_name(),
+			DELETED_LOGFILE_SUFFIX
+		))
+	}
+
 	pub fn list_logfiles(&self) -> Result<Vec<LogfilePath>, ::Error> {
 		let mut logfiles = Vec::<LogfilePath>::new();
 
-		for dirent in fs::read_dir(self.path.join("db"))? {
-			let dirent = dirent?;
-			logfiles.push(LogfilePath::from_file_name(
-				dirent.file_name().into_string()?,
-			));
+		for file_name in self.backend.list(&self.path.join(DATABASE_PATH))? {
+			if file_name.ends_with(DELETED_LOGFILE_SUFFIX) {
+				continue;
+			}
+
+			logfiles.push(LogfilePath::from_file_name(file_name));
 		}
 
 		Ok(logfiles)
@@ -0,0 +1,753 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use logfile::{SENSOR_ID_FILE_NAME, TRANSACTION_FILE_NAME};
+use logfile_directory::LogfileDirectory;
+use logfile_id::LogfileID;
+use logfile_partition::{self, LogfilePartition};
+use logfile_transaction::{LogfileTransaction, LogfileTransactionPartition};
+use measure::{Measurement, RecordFormat};
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use storage_backend::{FsStorageBackend, StorageBackend, StorageFile};
+
+const QUARANTINE_FILE_SUFFIX: &str = ".orphaned";
+
+// The fsck module checks logfiles for consistency and rebuilds damaged logfiles
+// from their partition files. The data directory is locked while it is checked,
+// so it can not be used while the data directory is opened by another instance.
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+	pub path: PathBuf,
+	pub sensor_id: Option<String>,
+	pub partitions: u64,
+	pub measurements: u64,
+	// the problems that were found in the logfile
+	pub errors: Vec<String>,
+	// set if the logfile was rebuilt by repair
+	pub repaired: bool,
+	// the number of bytes that were dropped from the partition files by repair
+	pub bytes_dropped: u64,
+	// the number of partition files that were not referenced by the transaction
+	// file and were renamed to "{file_name}.orphaned" by repair
+	pub files_quarantined: u64,
+}
+
+impl FsckReport {
+	pub fn is_ok(&self) -> bool {
+		self.errors.is_empty()
+	}
+}
+
+// the range of records in a partition file that could be decoded by walking the
+// file backwards from the end offset
+#[derive(Debug)]
+pub struct PartitionScan {
+	pub offset_begin: u64,
+	pub offset_end: u64,
+	pub measurements: u64,
+	pub time_min: Option<u64>,
+	pub time_max: Option<u64>,
+	pub time_last: Option<u64>,
+	pub is_ordered: bool,
+	pub error: Option<::Error>,
+}
+
+// checks all logfiles in the data directory
+pub fn verify(datadir: &Path) -> Result<Vec<FsckReport>, ::Error> {
+	let directory = LogfileDirectory::open(Arc::new(FsStorageBackend::new()), datadir)?;
+	verify_directory(&directory)
+}
+
+pub fn verify_directory(directory: &LogfileDirectory) -> Result<Vec<FsckReport>, ::Error> {
+	let backend = directory.get_backend();
+
+	let mut reports = Vec::<FsckReport>::new();
+	for logfile_path in directory.list_logfiles()? {
+		reports.push(verify_logfile(
+			&backend,
+			&directory.get_logfile_path(&logfile_path),
+		));
+	}
+
+	Ok(reports)
+}
+
+// repairs all damaged logfiles in the data directory. logfiles for which the
+// sensor id can not be determined from the sidecar file or the transaction file
+// are only repaired if the sensor id is passed in sensor_ids
+pub fn repair(datadir: &Path, sensor_ids: &[&str]) -> Result<Vec<FsckReport>, ::Error> {
+	let directory = LogfileDirectory::open(Arc::new(FsStorageBackend::new()), datadir)?;
+	repair_directory(&directory, sensor_ids)
+}
+
+pub fn repair_directory(
+	directory: &LogfileDirectory,
+	sensor_ids: &[&str],
+) -> Result<Vec<FsckReport>, ::Error> {
+	let backend = directory.get_backend();
+
+	let mut reports = Vec::<FsckReport>::new();
+	for logfile_path in directory.list_logfiles()? {
+		reports.push(repair_logfile(
+			&backend,
+			&directory.get_logfile_path(&logfile_path),
+			sensor_ids,
+		)?);
+	}
+
+	Ok(reports)
+}
+
+pub fn verify_logfile(backend: &Arc<dyn StorageBackend>, path: &Path) -> FsckReport {
+	let mut report = FsckReport {
+		path: path.to_owned(),
+		sensor_id: read_sensor_id_file(&**backend, path),
+		..Default::default()
+	};
+
+	let transaction_path = path.join(TRANSACTION_FILE_NAME);
+	if !backend.exists(&transaction_path) {
+		report.errors.push("missing transaction file".to_string());
+		return report;
+	}
+
+	let transaction = match LogfileTransaction::read_file(&**backend, &transaction_path) {
+		Ok(t) => t,
+		Err(e) => {
+			report.errors.push(e.message);
+			return report;
+		}
+	};
+
+	if let Some(ref sensor_id) = report.sensor_id {
+		if *sensor_id != transaction.id {
+			report
+				.errors
+				.push("sensor id file does not match the transaction file".to_string());
+		}
+	}
+
+	if !is_logfile_path_for(path, &transaction.id) {
+		report
+			.errors
+			.push("logfile directory does not match the sensor id".to_string());
+	}
+
+	report.sensor_id = Some(transaction.id.clone());
+
+	// check the partitions
+	let mut time_head_prev = None;
+	for partition in &transaction.partitions {
+		let partition = LogfilePartition::open(
+			backend,
+			path,
+			partition.sequence,
+			partition.time_head,
+			partition.time_tail,
+			partition.offset,
+		);
+
+		verify_partition(&**backend, &partition, &mut report);
+
+		if let Some(time_head_prev) = time_head_prev {
+			if partition.get_time_tail() < time_head_prev {
+				report.errors.push(format!(
+					"partition {:?} overlaps with the previous partition",
+					partition.get_file_name()
+				));
+			}
+		}
+
+		time_head_prev = Some(partition.get_time_head());
+	}
+
+	if let Some(ref partition) = transaction.late_partition {
+		match ::logfile::get_late_partition_sequence(partition) {
+			Ok(sequence) => {
+				let partition = LogfilePartition::open_late(
+					backend,
+					path,
+					sequence,
+					partition.time_head,
+					partition.time_tail,
+					partition.offset,
+				);
+
+				verify_partition(&**backend, &partition, &mut report);
+			}
+			Err(e) => report.errors.push(e.message),
+		}
+	}
+
+	report
+}
+
+// rebuilds the transaction file of a damaged logfile from its partition files.
+// the partitions are truncated to the last record that can be decoded. if a
+// record in the middle of a partition is damaged, the records before it are
+// dropped
+pub fn repair_logfile(
+	backend: &Arc<dyn StorageBackend>,
+	path: &Path,
+	sensor_ids: &[&str],
+) -> Result<FsckReport, ::Error> {
+	let mut report = verify_logfile(backend, path);
+	if report.is_ok() {
+		return Ok(report);
+	}
+
+	// find the sensor id. the logfile directory name is derived from the sensor
+	// id, so candidates that do not match the directory are ignored
+	let sensor_id = sensor_ids
+		.iter()
+		.map(|id| id.to_string())
+		.chain(read_sensor_id_file(&**backend, path))
+		.chain(report.sensor_id.clone())
+		.find(|id| is_logfile_path_for(path, id));
+
+	let sensor_id = match sensor_id {
+		Some(id) => id,
+		None => {
+			report
+				.errors
+				.push("unknown sensor id; pass the sensor id to repair".to_string());
+			return Ok(report);
+		}
+	};
+
+	warn!(
+		"Repairing logfile; path={:?}, sensor_id={:?}",
+		path, sensor_id
+	);
+
+	// rebuild from the committed partition list if the transaction file can be
+	// read. partition files that are not on the list were never committed or
+	// were already dropped, so they are moved aside instead of being re-added.
+	// without a transaction file, every partition file is used
+	let transaction_committed =
+		LogfileTransaction::read_file(&**backend, &path.join(TRANSACTION_FILE_NAME)).ok();
+
+	let committed = transaction_committed
+		.as_ref()
+		.map(|transaction| get_committed_partitions(backend, path, transaction));
+
+	let mut partitions = Vec::<LogfileTransactionPartition>::new();
+	let mut late_partition: Option<LogfileTransactionPartition> = None;
+	let mut next_sequence = transaction_committed.map_or(0, |t| t.next_sequence);
+
+	report.partitions = 0;
+	report.measurements = 0;
+
+	if let Some(ref committed) = committed {
+		for (file_name, _) in committed {
+			if !backend.exists(&path.join(file_name)) {
+				report
+					.errors
+					.push(format!("committed partition {:?} is missing", file_name));
+			}
+		}
+	}
+
+	for file_name in backend.list(path)? {
+		if !logfile_partition::is_partition_file_name(&file_name) {
+			continue;
+		}
+
+		let committed_partition = match committed {
+			Some(ref committed) => match committed.iter().find(|(n, _)| *n == file_name) {
+				Some((_, partition)) => Some(partition),
+				None => {
+					quarantine_partition(&**backend, path, &file_name, &mut report)?;
+					continue;
+				}
+			},
+			None => None,
+		};
+
+		let partition_name = match logfile_partition::parse_partition_file_name(&file_name) {
+			Some(v) => v,
+			None => {
+				report
+					.errors
+					.push(format!("unrecognized partition file {:?}", file_name));
+				continue;
+			}
+		};
+
+		// legacy records are only accepted in partitions without a sequence
+		// number, see measure::RecordFormat
+		let format = match partition_name.sequence {
+			Some(_) => RecordFormat::Current,
+			None => RecordFormat::Legacy,
+		};
+
+		// records after the committed offset were never committed
+		let scan = match repair_partition(
+			&**backend,
+			&path.join(&file_name),
+			committed_partition.map(|p| p.offset),
+			format,
+			&mut report,
+		)? {
+			Some(scan) => scan,
+			None => continue,
+		};
+
+		report.partitions += 1;
+		report.measurements += scan.measurements;
+
+		if let Some(sequence) = partition_name.sequence {
+			next_sequence = next_sequence.max(sequence + 1);
+		}
+
+		let offset = scan.offset_end - scan.offset_begin;
+
+		if !partition_name.late {
+			// the time tail of a partition is only encoded in legacy file names
+			let time_tail = partition_name
+				.time_tail
+				.or(committed_partition.map(|p| p.time_tail))
+				.or(scan.time_min)
+				.unwrap_or(0);
+
+			partitions.push(LogfileTransactionPartition {
+				sequence: partition_name.sequence,
+				time_head: scan.time_last.unwrap_or(time_tail).max(time_tail),
+				time_tail,
+				offset,
+			});
+
+			continue;
+		}
+
+		// keep the most recent late partition if there are multiple
+		if let Some(ref partition) = late_partition {
+			report
+				.errors
+				.push("multiple late partitions; dropping the older partition".to_string());
+
+			if partition.sequence >= partition_name.sequence {
+				continue;
+			}
+		}
+
+		late_partition = Some(LogfileTransactionPartition {
+			sequence: partition_name.sequence,
+			time_head: scan.time_max.unwrap_or(0),
+			time_tail: scan.time_min.unwrap_or(0),
+			offset,
+		});
+	}
+
+	// partitions with the same time range are ordered by their sequence number.
+	// legacy partitions were created before any partition with a sequence number
+	partitions.sort_by_key(|p| (p.time_tail, p.sequence.map_or(0, |s| s + 1)));
+
+	let transaction = LogfileTransaction {
+		id: sensor_id.clone(),
+		partitions,
+		late_partition,
+		next_sequence,
+	};
+
+	transaction.write_file(&**backend, &path.join(TRANSACTION_FILE_NAME), true)?;
+
+	::fs_util::write_file_atomic(
+		&**backend,
+		&path.join(SENSOR_ID_FILE_NAME),
+		sensor_id.as_bytes(),
+		true,
+	)?;
+
+	info!(
+		"Repaired logfile; path={:?}, partitions={}, measurements={}, bytes_dropped={}",
+		path, report.partitions, report.measurements, report.bytes_dropped
+	);
+
+	report.sensor_id = Some(sensor_id);
+	report.repaired = true;
+	Ok(report)
+}
+
+fn verify_partition(
+	backend: &dyn StorageBackend,
+	partition: &LogfilePartition,
+	report: &mut FsckReport,
+) {
+	let path = partition.get_file_path();
+	let file_name = partition.get_file_name();
+	let offset = partition.get_file_offset();
+
+	let len = match backend.get_len(&path) {
+		Ok(len) => len.unwrap_or(0),
+		Err(_) => 0,
+	};
+
+	if len < offset {
+		report.errors.push(format!(
+			"partition {:?} is shorter than the committed offset",
+			file_name
+		));
+		return;
+	}
+
+	let scan = match scan_partition(backend, &path, offset, partition.get_record_format()) {
+		Ok(s) => s,
+		Err(e) => {
+			report
+				.errors
+				.push(format!("partition {:?}: {}", file_name, e.message));
+			return;
+		}
+	};
+
+	report.partitions += 1;
+	report.measurements += scan.measurements;
+
+	if let Some(e) = scan.error {
+		report.errors.push(format!(
+			"partition {:?}: {}",
+			file_name,
+			partition.annotate_error(e, scan.offset_begin).message
+		));
+		return;
+	}
+
+	if partition.is_late() {
+		if scan.measurements > 0
+			&& (scan.time_min != Some(partition.get_time_tail())
+				|| scan.time_max != Some(partition.get_time_head()))
+		{
+			report.errors.push(format!(
+				"partition {:?} does not match the committed time range",
+				file_name
+			));
+		}
+
+		return;
+	}
+
+	if !scan.is_ordered {
+		report.errors.push(format!(
+			"partition {:?} contains measurements that are out of order",
+			file_name
+		));
+	}
+
+	let is_in_range = match (scan.time_min, scan.time_last) {
+		(Some(time_min), Some(time_last)) => {
+			time_min >= partition.get_time_tail() && time_last == partition.get_time_head()
+		}
+		_ => true,
+	};
+
+	if !is_in_range {
+		report.errors.push(format!(
+			"partition {:?} does not match the committed time range",
+			file_name
+		));
+	}
+}
+
+// returns the file names of the partitions in the transaction along with their
+// committed state
+fn get_committed_partitions(
+	backend: &Arc<dyn StorageBackend>,
+	path: &Path,
+	transaction: &LogfileTransaction,
+) -> Vec<(String, LogfileTransactionPartition)> {
+	let mut committed = Vec::new();
+
+	for partition in &transaction.partitions {
+		let file_name = LogfilePartition::open(
+			backend,
+			path,
+			partition.sequence,
+			partition.time_head,
+			partition.time_tail,
+			partition.offset,
+		)
+		.get_file_name();
+
+		committed.push((file_name, partition.clone()));
+	}
+
+	if let Some(ref partition) = transaction.late_partition {
+		if let Some(sequence) = partition.sequence {
+			let file_name = LogfilePartition::open_late(
+				backend,
+				path,
+				sequence,
+				partition.time_head,
+				partition.time_tail,
+				partition.offset,
+			)
+			.get_file_name();
+
+			committed.push((file_name, partition.clone()));
+		}
+	}
+
+	committed
+}
+
+// moves a partition file that is not referenced by the transaction file out of
+// the way. the new name is not a partition file name, so the file is neither
+// read nor deleted by recovery
+fn quarantine_partition(
+	backend: &dyn StorageBackend,
+	path: &Path,
+	file_name: &str,
+	report: &mut FsckReport,
+) -> Result<(), ::Error> {
+	let file_name_quarantined = format!("{}{}", file_name, QUARANTINE_FILE_SUFFIX);
+
+	warn!(
+		"Moving uncommitted partition aside; path={:?}, file_name={:?}",
+		path.join(file_name),
+		file_name_quarantined
+	);
+
+	backend.rename(&path.join(file_name), &path.join(&file_name_quarantined))?;
+	report.files_quarantined += 1;
+	Ok(())
+}
+
+// truncates the partition file to the records that can be decoded and that end
+// at or before offset_max. returns None if no record could be decoded, in which
+// case the partition file is deleted
+fn repair_partition(
+	backend: &dyn StorageBackend,
+	path: &Path,
+	offset_max: Option<u64>,
+	format: RecordFormat,
+	report: &mut FsckReport,
+) -> Result<Option<PartitionScan>, ::Error> {
+	let len = backend.get_len(path)?.unwrap_or(0);
+	let offset_max = offset_max.map_or(len, |offset| offset.min(len));
+
+	let mut file = open_file(backend, path)?;
+	let scan = match find_record_end(&mut file, offset_max, format, &HashSet::new())? {
+		Some(offset_end) => Some(scan_partition(backend, path, offset_end, format)?),
+		None => None,
+	};
+
+	let scan = match scan {
+		Some(scan) => scan,
+		None => {
+			warn!("Deleting partition without valid records; path={:?}", path);
+			backend.remove(path)?;
+			report.bytes_dropped += len;
+			return Ok(None);
+		}
+	};
+
+	if scan.offset_begin > 0 {
+		warn!(
+			"Dropping damaged records from partition; path={:?}, offset={}",
+			path, scan.offset_begin
+		);
+
+		let mut data = vec![0u8; (scan.offset_end - scan.offset_begin) as usize];
+		backend.read_at(path, scan.offset_begin, &mut data)?;
+
+		::fs_util::write_file_atomic(backend, path, &data, true)?;
+	} else if len > scan.offset_end {
+		warn!(
+			"Truncating partition; path={:?}, offset={}, len={}",
+			path, scan.offset_end, len
+		);
+
+		backend.truncate(path, scan.offset_end)?;
+		backend.sync(path)?;
+	}
+
+	report.bytes_dropped += len - (scan.offset_end - scan.offset_begin);
+	Ok(Some(scan))
+}
+
+// returns the largest offset at or before len at which a record ends that can be
+// decoded, skipping the offsets in offsets_failed. the file is searched byte by
+// byte, but most offsets are rejected by the version byte and the size in the
+// footer. only offsets that look like the end of a record cost a read of the
+// payload to verify the checksum
+fn find_record_end(
+	file: &mut Box<dyn StorageFile>,
+	len: u64,
+	format: RecordFormat,
+	offsets_failed: &HashSet<u64>,
+) -> Result<Option<u64>, ::Error> {
+	let mut offset = len;
+	while offset > 0 {
+		// zero bytes, e.g. from a file that was extended but never written, decode
+		// as empty legacy records with a time of zero
+		if !offsets_failed.contains(&offset)
+			&& Measurement::decode(file, offset, format).is_ok()
+			&& !(format == RecordFormat::Legacy && is_zero_footer(file, offset)?)
+		{
+			return Ok(Some(offset));
+		}
+
+		offset -= 1;
+	}
+
+	Ok(None)
+}
+
+fn is_zero_footer(file: &mut Box<dyn StorageFile>, offset: u64) -> Result<bool, ::Error> {
+	let mut footer = [0u8; ::measure::FOOTER_SIZE_LEGACY as usize];
+	file.seek(SeekFrom::Start(offset - footer.len() as u64))?;
+	file.read_exact(&mut footer)?;
+	Ok(footer.iter().all(|b| *b == 0))
+}
+
+// returns the longest run of records from the beginning of the partition file
+// that can be decoded, e.g. to clamp a partition whose tail was lost. the run
+// ends at or before len
+pub fn scan_intact_records(
+	backend: &dyn StorageBackend,
+	path: &Path,
+	len: u64,
+	format: RecordFormat,
+) -> Result<PartitionScan, ::Error> {
+	let mut file = open_file(backend, path)?;
+
+	// the ends of records from which the file can not be decoded back to the
+	// beginning. a candidate end offset whose records lead to one of them is
+	// given up right away, so every record is decoded about once instead of once
+	// for every candidate end offset above it
+	let mut offsets_failed = HashSet::<u64>::new();
+
+	let mut offset = len;
+	while offset > 0 {
+		let offset_end = match find_record_end(&mut file, offset, format, &offsets_failed)? {
+			Some(v) => v,
+			None => break,
+		};
+
+		let mut offsets = Vec::new();
+		let mut offset_begin = offset_end;
+		while offset_begin > 0 && !offsets_failed.contains(&offset_begin) {
+			match Measurement::decode(&mut file, offset_begin, format) {
+				Ok((_, size)) => {
+					offsets.push(offset_begin);
+					offset_begin -= size;
+				}
+				Err(_) => break,
+			}
+		}
+
+		if offset_begin == 0 {
+			return scan_partition(backend, path, offset_end, format);
+		}
+
+		offsets_failed.extend(offsets);
+		offset = offset_end - 1;
+	}
+
+	scan_partition(backend, path, 0, format)
+}
+
+// walks the partition backwards from the end offset until the beginning of the
+// file or the first record that can not be decoded
+fn scan_partition(
+	backend: &dyn StorageBackend,
+	path: &Path,
+	offset_end: u64,
+	format: RecordFormat,
+) -> Result<PartitionScan, ::Error> {
+	let mut scan = PartitionScan {
+		offset_begin: offset_end,
+		offset_end,
+		measurements: 0,
+		time_min: None,
+		time_max: None,
+		time_last: None,
+		is_ordered: true,
+		error: None,
+	};
+
+	if offset_end == 0 {
+		return Ok(scan);
+	}
+
+	let mut file = open_file(backend, path)?;
+	let mut time_next: Option<u64> = None;
+
+	while scan.offset_begin > 0 {
+		let (measurement, size) = match Measurement::decode(&mut file, scan.offset_begin, format) {
+			Ok(v) => v,
+			Err(e) => {
+				scan.error = Some(e);
+				break;
+			}
+		};
+
+		if let Some(time_next) = time_next {
+			if measurement.time > time_next {
+				scan.is_ordered = false;
+			}
+		}
+
+		if scan.time_last.is_none() {
+			scan.time_last = Some(measurement.time);
+		}
+
+		scan.time_min = Some(
+			scan.time_min
+				.map_or(measurement.time, |t| t.min(measurement.time)),
+		);
+		scan.time_max = Some(
+			scan.time_max
+				.map_or(measurement.time, |t| t.max(measurement.time)),
+		);
+		scan.measurements += 1;
+		scan.offset_begin -= size;
+		time_next = Some(measurement.time);
+	}
+
+	Ok(scan)
+}
+
+fn open_file(backend: &dyn StorageBackend, path: &Path) -> Result<Box<dyn StorageFile>, ::Error> {
+	match backend.open(path)? {
+		Some(f) => Ok(f),
+		None => Err(err_server!("file not found: {:?}", path)),
+	}
+}
+
+fn read_sensor_id_file(backend: &dyn StorageBackend, path: &Path) -> Option<String> {
+	match backend.read(&path.join(SENSOR_ID_FILE_NAME)) {
+		Ok(Some(data)) => String::from_utf8(data).ok(),
+		_ => None,
+	}
+}
+
+fn is_logfile_path_for(path: &Path, sensor_id: &str) -> bool {
+	let logfile_id = LogfileID::from_string(sensor_id.to_string());
+
+	match path.file_name().and_then(|n| n.to_str()) {
+		Some(file_name) => file_name == logfile_id.get_path().get_file_name(),
+		None => false,
+	}
+}
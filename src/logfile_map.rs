@@ -18,36 +18,152 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
-use logfile::Logfile;
+use durability::FlushReport;
+use eviction::{EvictionPolicy, OldestFirstPolicy};
+use logfile::{Logfile, LogfileUsage};
 use logfile_config::LogfileConfig;
 use logfile_directory::LogfileDirectory;
 use logfile_id::LogfileID;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use measure::Measurement;
+use quota::StorageQuota;
+use quota_rule::{QuotaExplanation, QuotaGroupUsage, QuotaSource};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use time::Clock;
 
 #[derive(Debug, Clone)]
 pub struct LogfileMap {
 	directory: LogfileDirectory,
-	config: LogfileConfig,
+	config: Arc<RwLock<LogfileConfig>>,
+	clock: Arc<dyn Clock>,
+	// the storage quotas that were changed at runtime. these are persisted in the
+	// data directory and override the quotas from the config
+	quota_overrides: Arc<Mutex<BTreeMap<String, StorageQuota>>>,
+	// the config without the quota overrides, to restore the quota of a sensor
+	// once its override is dropped
+	config_initial: Arc<LogfileConfig>,
 	logfiles: Arc<RwLock<HashMap<String, Arc<Logfile>>>>,
+	// logfiles that could not be loaded. these must be repaired before new data
+	// can be stored for the sensor, see logfile_fsck
+	logfiles_damaged: Arc<RwLock<HashSet<String>>>,
+	// serializes evictions, so that concurrent callers don't evict the same
+	// excess twice
+	eviction_lock: Arc<Mutex<()>>,
+	// running totals of the storage used by all logfiles and by each quota group,
+	// so that the storage limits can be checked without summing up the usage of
+	// all logfiles after every change
+	storage_totals: Arc<Mutex<StorageTotals>>,
+}
+
+#[derive(Debug, Default)]
+struct StorageTotals {
+	logfile_bytes: HashMap<String, u64>,
+	bytes: u64,
+	// indexed like the quota groups in the config
+	group_bytes: Vec<u64>,
 }
 
 impl LogfileMap {
-	pub fn open(directory: LogfileDirectory, config: LogfileConfig) -> Result<LogfileMap, ::Error> {
+	pub fn open(
+		directory: LogfileDirectory,
+		mut config: LogfileConfig,
+		clock: Arc<dyn Clock>,
+	) -> Result<LogfileMap, ::Error> {
 		let mut logfile_map = HashMap::<String, Arc<Logfile>>::new();
+		let config_initial = Arc::new(config.clone());
+
+		let quota_overrides = directory.read_quota_overrides()?;
+		for (sensor_id, quota) in &quota_overrides {
+			let logfile_id = LogfileID::from_string(sensor_id.to_string());
+			config.set_storage_quota_for(&logfile_id, quota.clone());
+		}
+
+		// read-only maps load logfiles on demand and never write to the directory,
+		// so the recovery pass is skipped
+		if directory.is_read_only() {
+			info!(
+				"Opening logfile database at {:?} in read-only mode",
+				directory.path
+			);
+
+			return Ok(LogfileMap {
+				directory,
+				config: Arc::new(RwLock::new(config)),
+				clock,
+				quota_overrides: Arc::new(Mutex::new(quota_overrides)),
+				config_initial,
+				logfiles: Arc::new(RwLock::new(logfile_map)),
+				logfiles_damaged: Arc::new(RwLock::new(HashSet::new())),
+				eviction_lock: Arc::new(Mutex::new(())),
+				storage_totals: Arc::new(Mutex::new(StorageTotals::default())),
+			});
+		}
 
 		info!("Opening logfile database at {:?}", directory.path);
-		for logfile in directory.list_logfiles()? {
-			if let Some(logfile) = directory.load_logfile(&logfile, &config)? {
-				logfile_map.insert(logfile.get_id().get_string(), logfile);
+		let mut logfiles_damaged = HashSet::<String>::new();
+		let mut recovery_report = directory.purge_deleted_logfiles()?;
+		for logfile_path in directory.list_logfiles()? {
+			let logfile = directory
+				.load_logfile(&logfile_path, &config, &clock)
+				.and_then(|logfile| match logfile {
+					Some(logfile) => Ok(Some((logfile.recover()?, logfile))),
+					None => Ok(None),
+				});
+
+			match logfile {
+				Ok(Some((report, logfile))) => {
+					recovery_report.merge(&report);
+					logfile_map.insert(logfile.get_id().get_string(), logfile);
+				}
+				Ok(None) => {
+					recovery_report.merge(&directory.recover_uncommitted_logfile(&logfile_path)?);
+				}
+				Err(e) => {
+					error!(
+						"Skipping damaged logfile {:?}: {}; run repair to restore it",
+						logfile_path.get_file_name(),
+						e
+					);
+
+					logfiles_damaged.insert(logfile_path.get_file_name());
+				}
 			}
 		}
 
-		Ok(LogfileMap {
+		if !recovery_report.is_clean() {
+			warn!(
+				"Recovered logfile database after an unclean shutdown; {:?}",
+				recovery_report
+			);
+		}
+
+		let storage_totals = StorageTotals {
+			group_bytes: vec![0; config.get_quota_groups().len()],
+			..Default::default()
+		};
+
+		let logfiles = logfile_map.values().cloned().collect::<Vec<_>>();
+		let logfile_map = LogfileMap {
 			directory,
-			config,
+			config: Arc::new(RwLock::new(config)),
+			clock,
+			quota_overrides: Arc::new(Mutex::new(quota_overrides)),
+			config_initial,
 			logfiles: Arc::new(RwLock::new(logfile_map)),
-		})
+			logfiles_damaged: Arc::new(RwLock::new(logfiles_damaged)),
+			eviction_lock: Arc::new(Mutex::new(())),
+			storage_totals: Arc::new(Mutex::new(storage_totals)),
+		};
+
+		for logfile in &logfiles {
+			logfile_map.update_storage_totals(&logfile.get_id(), Some(logfile));
+		}
+
+		// the storage limits might have been lowered since the last run
+		logfile_map.enforce_storage_limits()?;
+
+		Ok(logfile_map)
 	}
 
 	pub fn lookup(self: &LogfileMap, logfile_id: &LogfileID) -> Option<Arc<Logfile>> {
@@ -59,10 +175,33 @@ impl LogfileMap {
 		logfiles_locked.get(&logfile_id.get_string()).cloned()
 	}
 
+	// returns the logfile for reading. in read-only mode, the logfile is loaded
+	// from the last committed transaction on every call, so that data committed
+	// by the writing process becomes visible
+	pub fn lookup_snapshot(
+		self: &LogfileMap,
+		logfile_id: &LogfileID,
+	) -> Result<Option<Arc<Logfile>>, ::Error> {
+		if !self.is_read_only() {
+			return Ok(self.lookup(logfile_id));
+		}
+
+		self.directory
+			.load_logfile(&logfile_id.get_path(), &self.get_config(), &self.clock)
+	}
+
+	pub fn is_read_only(&self) -> bool {
+		self.directory.is_read_only()
+	}
+
 	pub fn lookup_or_create(
 		self: &LogfileMap,
 		logfile_id: &LogfileID,
 	) -> Result<Arc<Logfile>, ::Error> {
+		if self.is_read_only() {
+			return Err(err_user!("the data directory is opened read-only"));
+		}
+
 		// rust RWLocks don't support upgrades. so we implement an optimistic
 		// fast path using a read lock
 		if let Some(logfile) = self.lookup(&logfile_id) {
@@ -80,19 +219,442 @@ impl LogfileMap {
 			return Ok(logfile.clone());
 		}
 
+		// never create a new logfile on top of a damaged one
+		if self.is_damaged(logfile_id) {
+			return Err(err_corrupt!(
+				"logfile for sensor {:?} is damaged; run repair to restore it",
+				logfile_id.get_string()
+			));
+		}
+
 		// if the logfile doesn't exist yet, create a new one
-		let logfile = self.directory.create_logfile(logfile_id, &self.config)?;
+		let logfile = self
+			.directory
+			.create_logfile(logfile_id, &self.get_config(), &self.clock)?;
 		logfiles_locked.insert(logfile_id.get_string(), logfile.clone());
 		Ok(logfile)
 	}
 
-	pub fn set_storage_quota_for(&mut self, logfile_id: &LogfileID, quota: ::quota::StorageQuota) {
-		self.config.set_storage_quota_for(&logfile_id, quota);
-		// grab write lock
+	fn is_damaged(&self, logfile_id: &LogfileID) -> bool {
+		let logfiles_damaged_locked = match self.logfiles_damaged.read() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		logfiles_damaged_locked.contains(&logfile_id.get_path().get_file_name())
+	}
+
+	// deletes the logfile and all of its files. damaged logfiles can be deleted
+	// as well. deleting a logfile that does not exist is not an error. a storage
+	// quota that was set for the sensor at runtime is dropped as well
+	pub fn delete(&self, logfile_id: &LogfileID) -> Result<(), ::Error> {
+		if self.is_read_only() {
+			return Err(err_user!("the data directory is opened read-only"));
+		}
+
+		// locked before the logfiles, like in set_storage_quota_for
+		let mut quota_overrides_locked = match self.quota_overrides.lock() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		// the write lock is held until the files are deleted, so that the logfile
+		// can not be created again in the meantime
 		let mut logfiles_locked = match self.logfiles.write() {
 			Ok(l) => l,
 			Err(_) => fatal!("lock is poisoned"),
 		};
-		logfiles_locked.remove(&logfile_id.get_string());
+
+		let mut logfiles_damaged_locked = match self.logfiles_damaged.write() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		if let Some(logfile) = logfiles_locked.remove(&logfile_id.get_string()) {
+			logfile.delete();
+			self.update_storage_totals(logfile_id, None);
+		}
+
+		let logfile_path = logfile_id.get_path();
+		match self.directory.delete_logfile(&logfile_path) {
+			Ok(_) => {
+				logfiles_damaged_locked.remove(&logfile_path.get_file_name());
+			}
+			Err(e) => {
+				// the deletion was not committed if the logfile directory is still
+				// in place, so the logfile is loaded again
+				if let Ok(Some(logfile)) =
+					self.directory
+						.load_logfile(&logfile_path, &self.get_config(), &self.clock)
+				{
+					self.update_storage_totals(logfile_id, Some(&logfile));
+					logfiles_locked.insert(logfile_id.get_string(), logfile);
+				}
+
+				return Err(e);
+			}
+		}
+
+		if !quota_overrides_locked.contains_key(&logfile_id.get_string()) {
+			return Ok(());
+		}
+
+		let mut quota_overrides = quota_overrides_locked.clone();
+		quota_overrides.remove(&logfile_id.get_string());
+		self.directory.write_quota_overrides(&quota_overrides)?;
+		*quota_overrides_locked = quota_overrides;
+
+		// restore the quota from the config
+		let explanation = self.config_initial.explain_storage_quota_for(logfile_id);
+		match self.config.write() {
+			Ok(mut l) => match explanation.source {
+				QuotaSource::Sensor => l.set_storage_quota_for(logfile_id, explanation.quota),
+				_ => l.remove_storage_quota_for(logfile_id),
+			},
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		Ok(())
+	}
+
+	pub fn clear(&self) -> Result<(), ::Error> {
+		if self.is_read_only() {
+			return Err(err_user!("the data directory is opened read-only"));
+		}
+
+		let logfiles_locked = match self.logfiles.read() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		for logfile in logfiles_locked.values() {
+			let result = logfile.clear();
+			self.update_storage_totals(&logfile.get_id(), Some(logfile));
+			result?;
+		}
+
+		Ok(())
+	}
+
+	// appends the measurements to the logfile of the sensor, creating it if
+	// needed, and commits the transaction once, see Logfile::append_measurements
+	pub fn append_measurements(
+		&self,
+		logfile_id: &LogfileID,
+		measurements: &[Measurement],
+	) -> Result<(), ::Error> {
+		let logfile = self.lookup_or_create(logfile_id)?;
+		let result = logfile.append_measurements(measurements);
+		self.update_storage_totals(logfile_id, Some(&logfile));
+		result
+	}
+
+	// drops the measurements of the sensor with a time value in the range
+	// [from, until)
+	pub fn delete_range(
+		&self,
+		logfile_id: &LogfileID,
+		from: u64,
+		until: u64,
+	) -> Result<(), ::Error> {
+		if self.is_read_only() {
+			return Err(err_user!("the data directory is opened read-only"));
+		}
+
+		let logfile = match self.lookup(logfile_id) {
+			Some(logfile) => logfile,
+			None => return Ok(()),
+		};
+
+		let result = logfile.delete_range(from, until);
+		self.update_storage_totals(logfile_id, Some(&logfile));
+		result
+	}
+
+	// drops the measurements that are older than the max age of their sensor
+	pub fn expire(&self) -> Result<(), ::Error> {
+		if self.is_read_only() {
+			return Err(err_user!("the data directory is opened read-only"));
+		}
+
+		let logfiles_locked = match self.logfiles.read() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		for logfile in logfiles_locked.values() {
+			let result = logfile.expire();
+			self.update_storage_totals(&logfile.get_id(), Some(logfile));
+			result?;
+		}
+
+		Ok(())
+	}
+
+	// evicts partitions until the members of each quota group fit into the group
+	// quota and the total storage of all logfiles fits into the storage budget.
+	// within a group, the oldest partition of all members is evicted first. for
+	// the storage budget, the eviction policy decides which logfile gives up its
+	// oldest partition next. the limits are checked against the running totals,
+	// so the logfiles are only scanned if a limit is exceeded
+	pub fn enforce_storage_limits(&self) -> Result<(), ::Error> {
+		if self.is_read_only() {
+			return Err(err_user!("the data directory is opened read-only"));
+		}
+
+		let (quota_groups, storage_budget, eviction_policy) = {
+			let config = self.get_config();
+			(
+				config.get_quota_groups().to_vec(),
+				config.get_storage_budget(),
+				config.get_eviction_policy(),
+			)
+		};
+
+		let _eviction_locked = match self.eviction_lock.lock() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		// nested groups are enforced first, so that their evictions count towards
+		// the enclosing groups
+		let mut quota_groups = quota_groups.into_iter().enumerate().collect::<Vec<_>>();
+		quota_groups.sort_by_key(|(_, g)| Reverse(g.prefix.len()));
+
+		let mut evicted = Vec::<Arc<Logfile>>::new();
+		for (idx, quota_group) in &quota_groups {
+			let group_bytes = self.lock_storage_totals().group_bytes[*idx];
+			if !is_over_limit(&quota_group.quota, group_bytes) {
+				continue;
+			}
+
+			let members = self
+				.get_logfiles()
+				.into_iter()
+				.filter(|l| quota_group.contains(&l.get_id()))
+				.collect::<Vec<_>>();
+
+			let evicted_group =
+				evict_until(&members, &quota_group.quota, &OldestFirstPolicy::new());
+			for logfile in &evicted_group {
+				self.update_storage_totals(&logfile.get_id(), Some(logfile));
+			}
+
+			evicted.extend(evicted_group);
+		}
+
+		let bytes = self.lock_storage_totals().bytes;
+		if is_over_limit(&storage_budget, bytes) {
+			let evicted_budget =
+				evict_until(&self.get_logfiles(), &storage_budget, &*eviction_policy);
+			for logfile in &evicted_budget {
+				self.update_storage_totals(&logfile.get_id(), Some(logfile));
+			}
+
+			evicted.extend(evicted_budget);
+		}
+
+		// a logfile can be evicted from by several groups. the later commits have
+		// nothing left to do
+		for logfile in evicted {
+			logfile.commit()?;
+		}
+
+		Ok(())
+	}
+
+	// records the current storage usage of the logfile in the running totals.
+	// pass None once the logfile was deleted
+	fn update_storage_totals(&self, logfile_id: &LogfileID, logfile: Option<&Logfile>) {
+		// the usage is read while the totals are locked, so that concurrent
+		// updates of the same logfile can not record a stale value last
+		let mut totals = self.lock_storage_totals();
+
+		let bytes = logfile.map_or(0, |l| l.get_usage().bytes);
+		let bytes_prev = match logfile {
+			Some(_) => totals.logfile_bytes.insert(logfile_id.get_string(), bytes),
+			None => totals.logfile_bytes.remove(&logfile_id.get_string()),
+		}
+		.unwrap_or(0);
+
+		totals.bytes = (totals.bytes + bytes).saturating_sub(bytes_prev);
+
+		let config = self.get_config();
+		for (idx, quota_group) in config.get_quota_groups().iter().enumerate() {
+			if quota_group.contains(logfile_id) {
+				totals.group_bytes[idx] =
+					(totals.group_bytes[idx] + bytes).saturating_sub(bytes_prev);
+			}
+		}
+	}
+
+	fn lock_storage_totals(&self) -> MutexGuard<'_, StorageTotals> {
+		match self.storage_totals.lock() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		}
+	}
+
+	fn get_logfiles(&self) -> Vec<Arc<Logfile>> {
+		match self.logfiles.read() {
+			Ok(l) => l.values().cloned().collect(),
+			Err(_) => fatal!("lock is poisoned"),
+		}
+	}
+
+	// returns the storage used by each logfile, ordered by sensor id
+	pub fn get_usage(&self) -> Vec<LogfileUsage> {
+		let logfiles_locked = match self.logfiles.read() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		let mut usage = logfiles_locked
+			.values()
+			.map(|l| l.get_usage())
+			.collect::<Vec<_>>();
+
+		usage.sort_by_key(|u| u.id.get_string());
+		usage
 	}
+
+	// returns the storage used by the members of each quota group
+	pub fn get_group_usage(&self) -> Vec<QuotaGroupUsage> {
+		let usage = self.get_usage();
+
+		self.get_config()
+			.get_quota_groups()
+			.iter()
+			.map(|g| {
+				let members = usage.iter().filter(|u| g.contains(&u.id));
+				QuotaGroupUsage {
+					prefix: g.prefix.clone(),
+					quota: g.quota.clone(),
+					bytes: members.clone().fold(0, |s, u| s + u.bytes),
+					sensors: members.count() as u64,
+				}
+			})
+			.collect()
+	}
+
+	pub fn flush(&self) -> Result<FlushReport, ::Error> {
+		let logfiles_locked = match self.logfiles.read() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		let mut report = FlushReport::default();
+		for logfile in logfiles_locked.values() {
+			report.merge(&logfile.flush()?);
+		}
+
+		Ok(report)
+	}
+
+	// changes the storage quota of a sensor. the quota is persisted in the data
+	// directory and applied to the stored measurements right away
+	pub fn set_storage_quota_for(
+		&self,
+		logfile_id: &LogfileID,
+		quota: StorageQuota,
+	) -> Result<(), ::Error> {
+		if self.is_read_only() {
+			return Err(err_user!("the data directory is opened read-only"));
+		}
+
+		// the lock also serializes concurrent quota changes
+		let mut quota_overrides_locked = match self.quota_overrides.lock() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		let mut quota_overrides = quota_overrides_locked.clone();
+		quota_overrides.insert(logfile_id.get_string(), quota.clone());
+		self.directory.write_quota_overrides(&quota_overrides)?;
+		*quota_overrides_locked = quota_overrides;
+
+		match self.config.write() {
+			Ok(mut l) => l.set_storage_quota_for(logfile_id, quota.clone()),
+			Err(_) => fatal!("lock is poisoned"),
+		};
+
+		let logfile = match self.lookup(logfile_id) {
+			Some(logfile) => logfile,
+			None => return Ok(()),
+		};
+
+		let result = logfile.set_storage_quota(quota);
+		self.update_storage_totals(logfile_id, Some(&logfile));
+		result
+	}
+
+	pub fn explain_storage_quota_for(&self, logfile_id: &LogfileID) -> QuotaExplanation {
+		self.get_config().explain_storage_quota_for(logfile_id)
+	}
+
+	fn get_config(&self) -> RwLockReadGuard<'_, LogfileConfig> {
+		match self.config.read() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		}
+	}
+}
+
+fn is_over_limit(limit: &StorageQuota, bytes: u64) -> bool {
+	match *limit {
+		StorageQuota::Unlimited => false,
+		StorageQuota::Limited { limit_bytes } => bytes > limit_bytes,
+		StorageQuota::Zero => bytes > 0,
+	}
+}
+
+// evicts partitions of the logfiles until their total size fits into the limit
+// and returns the logfiles that were evicted from. the transactions are not
+// committed
+fn evict_until(
+	logfiles: &[Arc<Logfile>],
+	limit: &StorageQuota,
+	eviction_policy: &dyn EvictionPolicy,
+) -> Vec<Arc<Logfile>> {
+	let limit_bytes = match *limit {
+		StorageQuota::Unlimited => return Vec::new(),
+		StorageQuota::Limited { limit_bytes } => limit_bytes,
+		StorageQuota::Zero => 0,
+	};
+
+	let mut usage = logfiles.iter().map(|l| l.get_usage()).collect::<Vec<_>>();
+	let mut usage_bytes = usage.iter().fold(0, |s, u| s + u.bytes);
+	let mut evicted = vec![false; logfiles.len()];
+
+	while usage_bytes > limit_bytes {
+		let idx = match eviction_policy.select(&usage, limit_bytes) {
+			Some(idx) if idx < usage.len() && usage[idx].partitions > 0 => idx,
+			_ => break,
+		};
+
+		debug!(
+			"Evicting partition to meet the storage limit; id={:?}, usage={}, limit={}",
+			usage[idx].id, usage_bytes, limit_bytes
+		);
+
+		logfiles[idx].evict_partition();
+		let logfile_usage = logfiles[idx].get_usage();
+		usage_bytes = (usage_bytes + logfile_usage.bytes).saturating_sub(usage[idx].bytes);
+		usage[idx] = logfile_usage;
+		evicted[idx] = true;
+	}
+
+	if usage_bytes > limit_bytes {
+		warn!(
+			"Storage limit exceeded, but nothing left to evict; usage={}, limit={}",
+			usage_bytes, limit_bytes
+		);
+	}
+
+	logfiles
+		.iter()
+		.zip(evicted)
+		.filter(|(_, evicted)| *evicted)
+		.map(|(l, _)| l.clone())
+		.collect()
 }
@@ -18,25 +18,52 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
-use measure::Measurement;
-use std::fs;
+use measure::{Measurement, RecordFormat};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use storage_backend::{StorageBackend, StorageFile};
 
+const PARTITION_FILE_PREFIX: &str = "p";
+const PARTITION_FILE_SUFFIX: &str = ".log";
+const LATE_PARTITION_FILE_SUFFIX: &str = ".late.log";
+
+// Partition files are named after a sequence number that is unique within the
+// logfile, i.e. "p{sequence}.log" and "p{sequence}.late.log". Partitions that
+// were created before sequence numbers were introduced have no sequence number
+// and are named "{time_tail}.log" until they are migrated. Late partitions were
+// introduced after sequence numbers, so they always have one.
+//
+// A late partition is a side partition that holds measurements that arrived out
+// of order. Measurements in a late partition are not sorted by time; time_tail
+// and time_head store the smallest and largest time in the partition.
 #[derive(Debug, Clone)]
 pub struct LogfilePartition {
+	backend: Arc<dyn StorageBackend>,
 	path: PathBuf,
+	sequence: Option<u64>,
 	time_head: u64,
 	time_tail: u64,
 	offset: u64,
+	late: bool,
+	dirty: bool,
 }
 
 impl LogfilePartition {
-	pub fn create(path: &Path, time: u64) -> Result<LogfilePartition, ::Error> {
+	pub fn create(
+		backend: &Arc<dyn StorageBackend>,
+		path: &Path,
+		sequence: u64,
+		time: u64,
+	) -> Result<LogfilePartition, ::Error> {
 		let partition = LogfilePartition {
+			backend: backend.clone(),
 			path: path.to_owned(),
+			sequence: Some(sequence),
 			time_head: time,
 			time_tail: time,
 			offset: 0,
+			late: false,
+			dirty: false,
 		};
 
 		info!(
@@ -46,12 +73,67 @@ impl LogfilePartition {
 		Ok(partition)
 	}
 
-	pub fn open(path: &Path, time_head: u64, time_tail: u64, offset: u64) -> LogfilePartition {
+	pub fn create_late(
+		backend: &Arc<dyn StorageBackend>,
+		path: &Path,
+		sequence: u64,
+		time: u64,
+	) -> Result<LogfilePartition, ::Error> {
+		let partition = LogfilePartition {
+			backend: backend.clone(),
+			path: path.to_owned(),
+			sequence: Some(sequence),
+			time_head: time,
+			time_tail: time,
+			offset: 0,
+			late: true,
+			dirty: false,
+		};
+
+		info!(
+			"Creating new late logfile partition; path={:?}",
+			partition.get_file_path()
+		);
+		Ok(partition)
+	}
+
+	pub fn open(
+		backend: &Arc<dyn StorageBackend>,
+		path: &Path,
+		sequence: Option<u64>,
+		time_head: u64,
+		time_tail: u64,
+		offset: u64,
+	) -> LogfilePartition {
+		LogfilePartition {
+			backend: backend.clone(),
+			path: path.to_owned(),
+			sequence,
+			time_head,
+			time_tail,
+			offset,
+			late: false,
+			dirty: false,
+		}
+	}
+
+	pub fn open_late(
+		backend: &Arc<dyn StorageBackend>,
+		path: &Path,
+		sequence: u64,
+		time_head: u64,
+		time_tail: u64,
+		offset: u64,
+	) -> LogfilePartition {
 		LogfilePartition {
+			backend: backend.clone(),
 			path: path.to_owned(),
+			sequence: Some(sequence),
 			time_head,
 			time_tail,
 			offset,
+			late: true,
+			dirty: false,
 		}
 	}
 
@@ -68,29 +150,212 @@ impl LogfilePartition {
 			measurement.time, self.offset
 		);
 
-		self.offset += ::logfile_writer::append(&self.get_file_path(), self.offset, measurement)?;
+		self.offset += ::logfile_writer::append(
+			&*self.backend,
+			&self.get_file_path(),
+			self.offset,
+			measurement,
+		)?;
+		self.dirty = true;
 
 		self.time_head = measurement.time;
 		Ok(())
 	}
 
-	pub fn delete(&self) -> Result<(), ::Error> {
-		info!(
-			"Deleting logfile partition; path={:?}",
-			self.get_file_path()
+	pub fn append_late_measurement(&mut self, measurement: &Measurement) -> Result<(), ::Error> {
+		if !self.late {
+			return Err(err_server!("not a late partition"));
+		}
+
+		debug!(
+			"Storing new late measurement; time={}, foffset={}",
+			measurement.time, self.offset
 		);
-		fs::remove_file(self.get_file_path())?;
+
+		let is_empty = self.offset == 0;
+		self.offset += ::logfile_writer::append(
+			&*self.backend,
+			&self.get_file_path(),
+			self.offset,
+			measurement,
+		)?;
+		self.dirty = true;
+
+		if is_empty || measurement.time < self.time_tail {
+			self.time_tail = measurement.time;
+		}
+
+		if is_empty || measurement.time > self.time_head {
+			self.time_head = measurement.time;
+		}
+
 		Ok(())
 	}
 
+	// syncs the measurements that were appended since the last sync to disk
+	pub fn sync(&mut self) -> Result<(), ::Error> {
+		if self.dirty {
+			::logfile_writer::sync(&*self.backend, &self.get_file_path())?;
+			self.dirty = false;
+		}
+
+		Ok(())
+	}
+
+	// moves a partition that uses the legacy naming scheme to the given sequence
+	// number. the records are copied to the new file name in the current record
+	// format, so the old file stays valid until the new name is committed.
+	// returns the path of the old file, which must be deleted after the commit
+	pub fn migrate(&mut self, sequence: u64) -> Result<Option<PathBuf>, ::Error> {
+		if self.sequence.is_some() {
+			return Ok(None);
+		}
+
+		let path_legacy = self.get_file_path();
+		if !self.backend.exists(&path_legacy) {
+			self.sequence = Some(sequence);
+			return Ok(None);
+		}
+
+		let measurements = self.read_measurements()?;
+		self.sequence = Some(sequence);
+
+		let path = self.get_file_path();
+		if self.backend.exists(&path) {
+			self.backend.remove(&path)?;
+		}
+
+		info!(
+			"Migrating logfile partition; path={:?}, new_path={:?}",
+			path_legacy, path
+		);
+
+		self.offset = 0;
+		for measurement in &measurements {
+			self.offset +=
+				::logfile_writer::append(&*self.backend, &path, self.offset, measurement)?;
+		}
+
+		self.dirty = true;
+		Ok(Some(path_legacy))
+	}
+
+	// drops the records past the given offset after the partition file lost data.
+	// the time range is narrowed to the remaining records, which were scanned by
+	// logfile_fsck::scan_intact_records
+	pub fn clamp(
+		&mut self,
+		offset: u64,
+		time_min: Option<u64>,
+		time_max: Option<u64>,
+		time_last: Option<u64>,
+	) {
+		self.offset = offset;
+
+		if self.late {
+			self.time_tail = time_min.unwrap_or(self.time_tail);
+			self.time_head = time_max.unwrap_or(self.time_tail);
+		} else {
+			self.time_head = time_last.unwrap_or(self.time_tail);
+		}
+	}
+
+	pub fn delete(&self) -> Result<(), ::Error> {
+		let path = self.get_file_path();
+		info!("Deleting logfile partition; path={:?}", path);
+
+		// empty partitions may not have a file
+		if self.offset == 0 && !self.backend.exists(&path) {
+			return Ok(());
+		}
+
+		self.backend.remove(&path)
+	}
+
+	// opens the partition file for reading. returns None if the partition file
+	// does not exist
+	pub fn open_file(&self) -> Result<Option<Box<dyn StorageFile>>, ::Error> {
+		self.backend.open(&self.get_file_path())
+	}
+
+	// reads all measurements of the partition in the order in which they were
+	// stored
+	pub fn read_measurements(&self) -> Result<Vec<Measurement>, ::Error> {
+		let mut measurements = Vec::<Measurement>::new();
+		if self.offset == 0 {
+			return Ok(measurements);
+		}
+
+		let mut file = match self.open_file()? {
+			Some(f) => f,
+			None => {
+				return Err(err_server!(
+					"partition file not found: {:?}",
+					self.get_file_path()
+				))
+			}
+		};
+
+		let mut offset = self.offset;
+		while offset > 0 {
+			let (measurement, measurement_size) =
+				match Measurement::decode(&mut file, offset, self.get_record_format()) {
+					Ok(v) => v,
+					Err(e) => return Err(self.annotate_error(e, offset)),
+				};
+
+			measurements.push(measurement);
+			offset -= measurement_size;
+		}
+
+		measurements.reverse();
+		Ok(measurements)
+	}
+
+	// adds the partition path and offset to corruption errors so that the damaged
+	// record can be located
+	pub fn annotate_error(&self, error: ::Error, offset: u64) -> ::Error {
+		match error.code {
+			::ErrorCode::CorruptionError => err_corrupt!(
+				"corrupt measurement in partition {:?} at offset {}: {}",
+				self.get_file_path(),
+				offset,
+				error.message
+			),
+			_ => error,
+		}
+	}
+
 	pub fn get_file_name(&self) -> String {
-		return format!("{}.log", self.time_tail);
+		let suffix = if self.late {
+			LATE_PARTITION_FILE_SUFFIX
+		} else {
+			PARTITION_FILE_SUFFIX
+		};
+
+		match self.sequence {
+			Some(sequence) => format!("{}{}{}", PARTITION_FILE_PREFIX, sequence, suffix),
+			None => format!("{}{}", self.time_tail, PARTITION_FILE_SUFFIX),
+		}
 	}
 
 	pub fn get_file_path(&self) -> PathBuf {
 		self.path.join(self.get_file_name())
 	}
 
+	pub fn get_sequence(&self) -> Option<u64> {
+		self.sequence
+	}
+
+	// legacy records are only accepted in partitions that were created before
+	// sequence numbers were introduced
+	pub fn get_record_format(&self) -> RecordFormat {
+		match self.sequence {
+			Some(_) => RecordFormat::Current,
+			None => RecordFormat::Legacy,
+		}
+	}
+
 	pub fn get_file_offset(&self) -> u64 {
 		self.offset
 	}
@@ -102,4 +367,55 @@ impl LogfilePartition {
 	pub fn get_time_tail(&self) -> u64 {
 		self.time_tail
 	}
+
+	pub fn is_late(&self) -> bool {
+		self.late
+	}
+}
+
+pub fn is_partition_file_name(file_name: &str) -> bool {
+	file_name.ends_with(PARTITION_FILE_SUFFIX)
+}
+
+// The information encoded in a partition file name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionFileName {
+	pub sequence: Option<u64>,
+	pub time_tail: Option<u64>,
+	pub late: bool,
+}
+
+pub fn parse_partition_file_name(file_name: &str) -> Option<PartitionFileName> {
+	if !is_partition_file_name(file_name) {
+		return None;
+	}
+
+	if !file_name.starts_with(PARTITION_FILE_PREFIX) {
+		let time_tail = file_name
+			.trim_end_matches(PARTITION_FILE_SUFFIX)
+			.parse::<u64>()
+			.ok()?;
+
+		return Some(PartitionFileName {
+			sequence: None,
+			time_tail: Some(time_tail),
+			late: false,
+		});
+	}
+
+	let late = file_name.ends_with(LATE_PARTITION_FILE_SUFFIX);
+	let sequence = file_name[PARTITION_FILE_PREFIX.len()..]
+		.trim_end_matches(if late {
+			LATE_PARTITION_FILE_SUFFIX
+		} else {
+			PARTITION_FILE_SUFFIX
+		})
+		.parse::<u64>()
+		.ok()?;
+
+	Some(PartitionFileName {
+		sequence: Some(sequence),
+		time_tail: None,
+		late,
+	})
 }
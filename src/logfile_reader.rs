@@ -18,80 +18,90 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
+use fetch::{FetchOptions, FetchResult};
+use logfile_cursor::MeasurementCursor;
 use logfile_partition::LogfilePartition;
-use measure::Measurement;
-use std::fs;
 
 #[derive(Debug, Clone)]
 pub struct LogfileReader<'a> {
 	partitions: &'a [LogfilePartition],
+	late_partition: Option<&'a LogfilePartition>,
 }
 
 impl<'a> LogfileReader<'a> {
-	pub fn new(partitions: &'a [LogfilePartition]) -> LogfileReader<'a> {
-		LogfileReader { partitions }
+	pub fn new(
+		partitions: &'a [LogfilePartition],
+		late_partition: Option<&'a LogfilePartition>,
+	) -> LogfileReader<'a> {
+		LogfileReader {
+			partitions,
+			late_partition,
+		}
 	}
 
-	pub fn fetch_measurements(
-		&self,
-		time_start: Option<u64>,
-		time_limit: Option<u64>,
-		limit: Option<u64>,
-	) -> Result<Vec<Measurement>, ::Error> {
-		let mut measurements = Vec::<Measurement>::new();
+	pub fn fetch_measurements(&self, options: &FetchOptions) -> Result<FetchResult, ::Error> {
+		let mut result = FetchResult::default();
+		let mut result_bytes = 0;
 
-		'scan: for partition in self.partitions.iter().rev() {
-			// skip partitions that are not part of the time range
-			if let Some(time_start) = time_start {
-				if partition.get_time_tail() > time_start {
-					continue;
-				}
-			}
-
-			// break if there is no further partition that can match the time range
-			if let Some(time_limit) = time_limit {
-				if partition.get_time_head() <= time_limit {
-					break 'scan;
-				}
-			}
+		let mut cursor = MeasurementCursor::open(
+			self.partitions.to_vec(),
+			self.late_partition.cloned(),
+			options,
+		)?;
+		loop {
+			// remember the position of the next measurement in case it exceeds the
+			// response size budget
+			let position = match options.max_bytes {
+				Some(_) => cursor.get_position(),
+				None => None,
+			};
 
-			let mut file = fs::File::open(partition.get_file_path())?;
-			let mut file_offset = partition.get_file_offset();
+			let measurement = match cursor.next() {
+				Some(measurement) => measurement?,
+				None => break,
+			};
 
-			while file_offset > 0 {
-				let measurement = Measurement::decode(&mut file, file_offset)?;
+			// stop once the response size budget is reached
+			let measurement_size = measurement.get_encoded_size();
+			if let Some(max_bytes) = options.max_bytes {
+				// a measurement that exceeds the budget on its own would never fit
+				// into a page, so it is skipped instead of failing every fetch
+				if measurement_size > max_bytes {
+					warn!(
+						"Skipping measurement that exceeds the fetch size limit; time={}, \
+						 size={}, max_bytes={}",
+						measurement.time, measurement_size, max_bytes
+					);
 
-				if measurement.get_encoded_size() <= file_offset {
-					file_offset -= measurement.get_encoded_size();
-				} else {
-					return Err(err_server!("corrupt file"));
-				}
-
-				if let Some(time_start) = time_start {
-					// skip measurements that are not part of the time range
-					if measurement.time > time_start {
-						continue;
-					}
+					result.skipped += 1;
+					result.truncated = true;
+					continue;
 				}
 
-				// break once the end of the time window is reached
-				if let Some(time_limit) = time_limit {
-					if measurement.time <= time_limit {
-						break 'scan;
-					}
+				if result_bytes + measurement_size > max_bytes {
+					result.continuation = position;
+					result.truncated = true;
+					return Ok(result);
 				}
+			}
 
-				measurements.push(measurement);
+			result_bytes += measurement_size;
+			result.measurements.push(measurement);
 
-				// break once limit is reached
-				if let Some(limit) = limit {
-					if measurements.len() as u64 == limit {
-						break 'scan;
-					}
+			// break once limit is reached
+			if let Some(limit) = options.limit {
+				if result.measurements.len() as u64 == limit {
+					break;
 				}
 			}
 		}
 
-		Ok(measurements)
+		// only return a continuation token if there are more measurements to fetch
+		let position = cursor.get_position();
+		if cursor.next().is_some() {
+			result.continuation = position;
+		}
+
+		Ok(result)
 	}
 }
@@ -0,0 +1,144 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use logfile_fsck;
+use logfile_partition::{self, LogfilePartition};
+use std::path::Path;
+use storage_backend::StorageBackend;
+
+const SWAP_FILE_SUFFIX: &str = ".swap";
+
+// Summarizes the changes made while reconciling logfile directories with their
+// last committed transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+	// partitions that contained uncommitted bytes past the committed offset
+	pub partitions_truncated: u64,
+	pub bytes_truncated: u64,
+	// partitions that are shorter than the committed offset, i.e. committed data
+	// was lost. these are clamped to the last record that can be decoded
+	pub partitions_incomplete: u64,
+	pub bytes_lost: u64,
+	// unreferenced partition files and stale swap files
+	pub files_deleted: u64,
+}
+
+impl RecoveryReport {
+	pub fn merge(&mut self, other: &RecoveryReport) {
+		self.partitions_truncated += other.partitions_truncated;
+		self.bytes_truncated += other.bytes_truncated;
+		self.partitions_incomplete += other.partitions_incomplete;
+		self.bytes_lost += other.bytes_lost;
+		self.files_deleted += other.files_deleted;
+	}
+
+	pub fn is_clean(&self) -> bool {
+		*self == RecoveryReport::default()
+	}
+}
+
+// reconciles the files in a logfile directory with the committed partitions:
+// partitions are truncated to their committed offset and all partition and swap
+// files that are not referenced by the transaction are deleted. partitions that
+// are shorter than their committed offset are clamped to the records that are
+// still intact; the caller must commit the clamped partitions
+pub fn recover(
+	backend: &dyn StorageBackend,
+	path: &Path,
+	partitions: &mut [&mut LogfilePartition],
+) -> Result<RecoveryReport, ::Error> {
+	let mut report = RecoveryReport::default();
+
+	if !backend.is_dir(path) {
+		return Ok(report);
+	}
+
+	// truncate partitions to the committed offset
+	for partition in partitions.iter_mut() {
+		let partition_path = partition.get_file_path();
+		let partition_offset = partition.get_file_offset();
+
+		let partition_len = backend.get_len(&partition_path)?.unwrap_or(0);
+
+		if partition_len > partition_offset {
+			warn!(
+				"Truncating uncommitted data from partition; path={:?}, offset={}, len={}",
+				partition_path, partition_offset, partition_len
+			);
+
+			backend.truncate(&partition_path, partition_offset)?;
+			backend.sync(&partition_path)?;
+
+			report.partitions_truncated += 1;
+			report.bytes_truncated += partition_len - partition_offset;
+		}
+
+		if partition_len < partition_offset {
+			let scan = logfile_fsck::scan_intact_records(
+				backend,
+				&partition_path,
+				partition_len,
+				partition.get_record_format(),
+			)?;
+
+			error!(
+				"Partition is shorter than the committed offset; path={:?}, offset={}, len={}, \
+				 offset_intact={}",
+				partition_path, partition_offset, partition_len, scan.offset_end
+			);
+
+			if partition_len > scan.offset_end {
+				backend.truncate(&partition_path, scan.offset_end)?;
+				backend.sync(&partition_path)?;
+			}
+
+			partition.clamp(
+				scan.offset_end,
+				scan.time_min,
+				scan.time_max,
+				scan.time_last,
+			);
+
+			report.partitions_incomplete += 1;
+			report.bytes_lost += partition_offset - scan.offset_end;
+		}
+	}
+
+	// delete unreferenced partition files and stale swap files
+	let partition_file_names = partitions
+		.iter()
+		.map(|p| p.get_file_name())
+		.collect::<Vec<_>>();
+
+	for file_name in backend.list(path)? {
+		let is_orphaned = file_name.ends_with(SWAP_FILE_SUFFIX)
+			|| (logfile_partition::is_partition_file_name(&file_name)
+				&& !partition_file_names.contains(&file_name));
+
+		if is_orphaned {
+			let file_path = path.join(&file_name);
+			warn!("Deleting orphaned file; path={:?}", file_path);
+			backend.remove(&file_path)?;
+			report.files_deleted += 1;
+		}
+	}
+
+	Ok(report)
+}
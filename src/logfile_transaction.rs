@@ -21,45 +21,64 @@
 use logfile_id::LogfileID;
 use logfile_partition::LogfilePartition;
 use serde_json as json;
-use std::fs;
-use std::io::{Read, Write};
 use std::path::Path;
+use storage_backend::StorageBackend;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogfileTransaction {
 	pub id: String,
 	pub partitions: Vec<LogfileTransactionPartition>,
+	#[serde(default)]
+	pub late_partition: Option<LogfileTransactionPartition>,
+	// the sequence number of the next partition that is created
+	#[serde(default)]
+	pub next_sequence: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogfileTransactionPartition {
+	// None for partitions that use the legacy naming scheme
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sequence: Option<u64>,
 	pub time_head: u64,
 	pub time_tail: u64,
 	pub offset: u64,
 }
 
 impl LogfileTransaction {
-	pub fn new(id: &LogfileID, partitions: &[LogfilePartition]) -> LogfileTransaction {
-		let partitions = partitions
-			.iter()
-			.map(|partition| LogfileTransactionPartition {
-				time_head: partition.get_time_head(),
-				time_tail: partition.get_time_tail(),
-				offset: partition.get_file_offset(),
-			});
-
+	pub fn new(
+		id: &LogfileID,
+		partitions: &[LogfilePartition],
+		late_partition: Option<&LogfilePartition>,
+		next_sequence: u64,
+	) -> LogfileTransaction {
 		LogfileTransaction {
 			id: id.get_string(),
-			partitions: partitions.collect(),
+			partitions: partitions.iter().map(Self::encode_partition).collect(),
+			late_partition: late_partition.map(Self::encode_partition),
+			next_sequence,
 		}
 	}
 
-	pub fn read_file(path: &Path) -> Result<LogfileTransaction, ::Error> {
-		let mut file = fs::File::open(path)?;
-		let mut data = String::new();
-		file.read_to_string(&mut data)?;
+	fn encode_partition(partition: &LogfilePartition) -> LogfileTransactionPartition {
+		LogfileTransactionPartition {
+			sequence: partition.get_sequence(),
+			time_head: partition.get_time_head(),
+			time_tail: partition.get_time_tail(),
+			offset: partition.get_file_offset(),
+		}
+	}
+
+	pub fn read_file(
+		backend: &dyn StorageBackend,
+		path: &Path,
+	) -> Result<LogfileTransaction, ::Error> {
+		let data = match backend.read(path)? {
+			Some(data) => data,
+			None => return Err(err_server!("transaction file not found: {:?}", path)),
+		};
 
-		let transaction = match json::from_str(&data) {
+		let transaction = match json::from_slice(&data) {
 			Ok(v) => v,
 			Err(e) => return Err(err_server!("error while decoding transaction file: {}", e)),
 		};
@@ -67,30 +86,17 @@ impl LogfileTransaction {
 		Ok(transaction)
 	}
 
-	pub fn write_file(&self, path: &Path) -> Result<(), ::Error> {
+	pub fn write_file(
+		&self,
+		backend: &dyn StorageBackend,
+		path: &Path,
+		sync: bool,
+	) -> Result<(), ::Error> {
 		let encoded = match json::to_vec(&self) {
 			Ok(v) => v,
 			Err(e) => return Err(err_server!("error while encoding transaction file: {}", e)),
 		};
 
-		let path_swap = format!(
-			"{}.swap",
-			match path.to_str() {
-				Some(v) => v,
-				None => return Err(err_server!("invalid transaction path")),
-			}
-		);
-
-		// write to swap file
-		{
-			let mut file = fs::File::create(&path_swap)?;
-			file.write_all(&encoded)?;
-			file.sync_data()?;
-		}
-
-		// replace transaction file with swap file
-		fs::rename(&path_swap, &path)?;
-
-		Ok(())
+		::fs_util::write_file_atomic(backend, path, &encoded, sync)
 	}
 }
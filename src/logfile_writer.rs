@@ -19,17 +19,26 @@
  * of said person’s immediate fault when using the work as intended.
  */
 use measure::Measurement;
-use std::fs;
+use std::io;
 use std::path::Path;
+use storage_backend::StorageBackend;
 
-pub fn append(path: &Path, offset: u64, measurement: &Measurement) -> Result<u64, ::Error> {
-	let mut file_opts = fs::OpenOptions::new();
-	file_opts.write(true);
-	file_opts.create(true);
+// writes the measurement at the given offset. the data is not synced to disk, call
+// sync before committing a transaction that references the new data
+pub fn append(
+	backend: &dyn StorageBackend,
+	path: &Path,
+	offset: u64,
+	measurement: &Measurement,
+) -> Result<u64, ::Error> {
+	let mut encoded = io::Cursor::new(Vec::<u8>::new());
+	measurement.encode(&mut encoded, 0)?;
 
-	let mut file = file_opts.open(&path)?;
-	measurement.encode(&mut file, offset)?;
-	file.sync_data()?;
+	backend.append(path, offset, encoded.get_ref())?;
 
 	Ok(measurement.get_encoded_size())
 }
+
+pub fn sync(backend: &dyn StorageBackend, path: &Path) -> Result<(), ::Error> {
+	backend.sync(path)
+}
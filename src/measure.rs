@@ -19,65 +19,103 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
+use base64;
+use checksum;
 use serde;
 use serde::ser::SerializeStruct;
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::mem;
 
-pub const FOOTER_SIZE: u64 = 12;
+// Each measurement is stored as a record that consists of the payload data
+// followed by a fixed-size footer. Records are read back-to-front, starting
+// from the end of the footer, so the format version is stored in the last byte.
+//
+// version 0 (legacy):  data | u32 data_size | u64 time
+// version 1:           data | u32 data_size | u64 time | u32 crc32 | u8 version
+//
+// The crc32 checksum covers the payload data, the data size and the time. Legacy
+// records do not store a version byte, but since the most significant byte of a
+// (little endian) microsecond timestamp is zero until the year 4253, a zero byte
+// at the end of a record identifies it as a legacy record. Since any run of zero
+// bytes would decode as legacy records, they are only accepted in partitions that
+// were written before the version byte was introduced, see RecordFormat. An empty
+// legacy record with a time of zero consists of zero bytes only. It is valid below
+// the committed offset of a partition, but is never taken as the end of a
+// partition whose committed offset is unknown, see logfile_fsck.
+pub const FOOTER_SIZE: u64 = 17;
+pub const FOOTER_SIZE_LEGACY: u64 = 12;
+
+const RECORD_VERSION_LEGACY: u8 = 0;
+const RECORD_VERSION: u8 = 1;
+
+// The record versions that may be stored in a partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+	// legacy and current records, for partitions without a sequence number
+	Legacy,
+	// current records only
+	Current,
+}
 
 #[derive(Debug, Clone)]
 pub struct Measurement {
 	pub time: u64,
-	pub data: String,
+	pub data: Vec<u8>,
 }
 
 impl Measurement {
-	pub fn decode<S: Read + Seek>(storage: &mut S, offset: u64) -> Result<Measurement, ::Error> {
-		if offset < FOOTER_SIZE {
-			return Err(err_server!("invalid offset"));
-		}
-
-		let mut footer = vec![0u8; FOOTER_SIZE as usize];
-		storage.seek(SeekFrom::Start(offset - FOOTER_SIZE as u64))?;
-		storage.read_exact(&mut footer)?;
-
-		let mut time_encoded: [u8; 8] = Default::default();
-		time_encoded.copy_from_slice(&footer[4..12]);
-
-		let time = u64::from_le(unsafe { mem::transmute_copy(&time_encoded) });
-
-		let mut data_size_encoded: [u8; 4] = Default::default();
-		data_size_encoded.copy_from_slice(&footer[0..4]);
-
-		let data_size = u32::from_le(unsafe { mem::transmute_copy(&data_size_encoded) });
-
-		if offset < FOOTER_SIZE + data_size as u64 {
-			return Err(err_server!("invalid offset"));
-		}
-
-		let data_offset = offset - data_size as u64 - FOOTER_SIZE as u64;
-
-		let mut data = vec![0; data_size as usize];
+	// decodes the record ending at the given offset. returns the measurement and
+	// the total size of the record on disk
+	pub fn decode<S: Read + Seek>(
+		storage: &mut S,
+		offset: u64,
+		format: RecordFormat,
+	) -> Result<(Measurement, u64), ::Error> {
+		let footer = RecordFooter::decode(storage, offset, format)?;
+		let data_offset = offset - footer.get_record_size();
+
+		let mut data = vec![0; footer.data_size as usize];
 
 		// N.B. there doesnt appear to be a binding to pread in the rust standard lib
 		storage.seek(SeekFrom::Start(data_offset))?;
 		storage.read_exact(&mut data)?;
 
-		let measurement = Measurement { time, data: String::from_utf8(data)? };
+		if let Some(checksum_stored) = footer.checksum {
+			let checksum = checksum::crc32_update(checksum::crc32(&data), &footer.header);
+			if checksum != checksum_stored {
+				return Err(err_corrupt!(
+					"checksum mismatch in record at offset {}",
+					offset
+				));
+			}
+		}
+
+		let measurement = Measurement {
+			time: footer.time,
+			data,
+		};
 
-		Ok(measurement)
+		Ok((measurement, footer.get_record_size()))
 	}
 
-	pub fn encode<S: Write + Seek>(&self, storage: &mut S, offset: u64) -> Result<(), ::Error> {
-		let time_encoded: [u8; 8] = unsafe { mem::transmute(self.time.to_le()) };
+	// returns the total size of the record ending at the given offset without
+	// reading the payload data
+	pub fn decode_size<S: Read + Seek>(
+		storage: &mut S,
+		offset: u64,
+		format: RecordFormat,
+	) -> Result<u64, ::Error> {
+		let footer = RecordFooter::decode(storage, offset, format)?;
+		Ok(footer.get_record_size())
+	}
 
-		let data_size_encoded: [u8; 4] =
-			unsafe { mem::transmute((self.data.len() as u32).to_le()) };
+	pub fn encode<S: Write + Seek>(&self, storage: &mut S, offset: u64) -> Result<(), ::Error> {
+		let mut encoded = self.data.clone();
+		encoded.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+		encoded.extend_from_slice(&self.time.to_le_bytes());
 
-		let mut encoded = self.data.clone().as_bytes().to_vec();
-		encoded.extend_from_slice(&data_size_encoded);
-		encoded.extend_from_slice(&time_encoded);
+		let checksum = checksum::crc32(&encoded);
+		encoded.extend_from_slice(&checksum.to_le_bytes());
+		encoded.push(RECORD_VERSION);
 
 		assert!(encoded.len() as u64 == self.get_encoded_size());
 
@@ -87,8 +125,96 @@ impl Measurement {
 		Ok(())
 	}
 
+	// returns the payload data as text if it is valid UTF-8
+	pub fn get_data_str(&self) -> Option<&str> {
+		::std::str::from_utf8(&self.data).ok()
+	}
+
 	pub fn get_encoded_size(&self) -> u64 {
-		(self.data.len() as u64 + FOOTER_SIZE) as u64
+		self.data.len() as u64 + FOOTER_SIZE
+	}
+}
+
+struct RecordFooter {
+	header: [u8; 12],
+	footer_size: u64,
+	data_size: u64,
+	time: u64,
+	checksum: Option<u32>,
+}
+
+impl RecordFooter {
+	fn decode<S: Read + Seek>(
+		storage: &mut S,
+		offset: u64,
+		format: RecordFormat,
+	) -> Result<RecordFooter, ::Error> {
+		if offset < FOOTER_SIZE_LEGACY {
+			return Err(err_corrupt!("invalid record offset {}", offset));
+		}
+
+		let mut version = [0u8; 1];
+		storage.seek(SeekFrom::Start(offset - 1))?;
+		storage.read_exact(&mut version)?;
+
+		let footer_size = match version[0] {
+			RECORD_VERSION_LEGACY if format == RecordFormat::Legacy => FOOTER_SIZE_LEGACY,
+			RECORD_VERSION => FOOTER_SIZE,
+			v => {
+				return Err(err_corrupt!(
+					"invalid record version {} at offset {}",
+					v,
+					offset
+				))
+			}
+		};
+
+		if offset < footer_size {
+			return Err(err_corrupt!("invalid record offset {}", offset));
+		}
+
+		let mut footer = vec![0u8; footer_size as usize];
+		storage.seek(SeekFrom::Start(offset - footer_size))?;
+		storage.read_exact(&mut footer)?;
+
+		let mut header = [0u8; 12];
+		header.copy_from_slice(&footer[0..12]);
+
+		let mut data_size_encoded = [0u8; 4];
+		data_size_encoded.copy_from_slice(&footer[0..4]);
+		let data_size = u64::from(u32::from_le_bytes(data_size_encoded));
+
+		let mut time_encoded = [0u8; 8];
+		time_encoded.copy_from_slice(&footer[4..12]);
+		let time = u64::from_le_bytes(time_encoded);
+
+		let checksum = if footer_size == FOOTER_SIZE {
+			let mut checksum_encoded = [0u8; 4];
+			checksum_encoded.copy_from_slice(&footer[12..16]);
+			Some(u32::from_le_bytes(checksum_encoded))
+		} else {
+			None
+		};
+
+		if offset < footer_size + data_size {
+			return Err(err_corrupt!(
+				"invalid record size {} at offset {}",
+				data_size,
+				offset
+			));
+		}
+
+		Ok(RecordFooter {
+			header,
+			footer_size,
+			data_size,
+			time,
+			checksum,
+		})
+	}
+
+	fn get_record_size(&self) -> u64 {
+		self.data_size + self.footer_size
 	}
 }
 
@@ -97,9 +223,14 @@ impl serde::Serialize for Measurement {
 	where
 		S: serde::ser::Serializer,
 	{
+		// payloads that are valid UTF-8 are serialized as text, all other payloads
+		// are serialized as base64 in a separate field
 		let mut state = serializer.serialize_struct("Measurement", 2)?;
 		state.serialize_field("time", &self.time)?;
-		state.serialize_field("data", &self.data)?;
+		match self.get_data_str() {
+			Some(text) => state.serialize_field("data", text)?,
+			None => state.serialize_field("data_base64", &base64::encode(&self.data))?,
+		};
 		state.end()
 	}
 }
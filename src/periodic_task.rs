@@ -0,0 +1,70 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// Runs a task from a background thread at a fixed interval. Once the periodic
+// task is dropped, the thread finishes the current run and exits.
+#[derive(Debug)]
+pub struct PeriodicTask {
+	name: String,
+	shutdown: Option<mpsc::Sender<()>>,
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+impl PeriodicTask {
+	pub fn start<F>(name: &str, interval: Duration, mut task: F) -> Result<PeriodicTask, ::Error>
+	where
+		F: FnMut() + Send + 'static,
+	{
+		let (shutdown, shutdown_recv) = mpsc::channel::<()>();
+
+		let thread = thread::Builder::new()
+			.name(name.to_string())
+			.spawn(move || {
+				// the channel is disconnected once the task is dropped
+				while let Err(mpsc::RecvTimeoutError::Timeout) =
+					shutdown_recv.recv_timeout(interval)
+				{
+					task();
+				}
+			})?;
+
+		Ok(PeriodicTask {
+			name: name.to_string(),
+			shutdown: Some(shutdown),
+			thread: Some(thread),
+		})
+	}
+}
+
+impl Drop for PeriodicTask {
+	fn drop(&mut self) {
+		self.shutdown.take();
+
+		if let Some(thread) = self.thread.take() {
+			if thread.join().is_err() {
+				error!("Thread {} panicked", self.name);
+			}
+		}
+	}
+}
@@ -0,0 +1,295 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use logfile_id::LogfileID;
+use quota::StorageQuota;
+
+// matches sensor ids against a prefix or a glob pattern. in glob patterns, `*`
+// matches any sequence of characters and `?` matches a single character
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaPattern {
+	Prefix(String),
+	Glob(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaRule {
+	pub pattern: QuotaPattern,
+	pub quota: StorageQuota,
+}
+
+// a storage quota that is shared by all sensors whose id starts with the prefix.
+// groups can be nested, e.g. a group for a machine within a group for a plant
+#[derive(Debug, Clone)]
+pub struct QuotaGroup {
+	pub prefix: String,
+	pub quota: StorageQuota,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaGroupUsage {
+	pub prefix: String,
+	pub quota: StorageQuota,
+	pub bytes: u64,
+	pub sensors: u64,
+}
+
+// where the storage quota of a sensor comes from, see
+// LogfileConfig::explain_storage_quota_for
+#[derive(Debug, Clone)]
+pub enum QuotaSource {
+	// a quota that was set for exactly this sensor id
+	Sensor,
+	// the quota rule at the given position in the list of rules
+	Rule { index: usize, rule: QuotaRule },
+	Default,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaExplanation {
+	pub quota: StorageQuota,
+	pub source: QuotaSource,
+}
+
+impl QuotaPattern {
+	pub fn matches(&self, logfile_id: &LogfileID) -> bool {
+		let id = logfile_id.get_string();
+		match self {
+			QuotaPattern::Prefix(prefix) => id.starts_with(prefix.as_str()),
+			QuotaPattern::Glob(glob) => {
+				let glob = glob.chars().collect::<Vec<_>>();
+				let id = id.chars().collect::<Vec<_>>();
+				glob_matches(&glob, &id)
+			}
+		}
+	}
+
+	// the number of literal characters in the pattern. if several rules match a
+	// sensor id, the rule with the most literal characters wins
+	pub fn get_specificity(&self) -> usize {
+		match self {
+			QuotaPattern::Prefix(prefix) => prefix.chars().count(),
+			QuotaPattern::Glob(glob) => glob.chars().filter(|c| *c != '*' && *c != '?').count(),
+		}
+	}
+}
+
+impl QuotaRule {
+	pub fn new(pattern: QuotaPattern, quota: StorageQuota) -> QuotaRule {
+		QuotaRule { pattern, quota }
+	}
+}
+
+impl QuotaGroup {
+	pub fn new(prefix: String, quota: StorageQuota) -> QuotaGroup {
+		QuotaGroup { prefix, quota }
+	}
+
+	pub fn contains(&self, logfile_id: &LogfileID) -> bool {
+		logfile_id.get_string().starts_with(self.prefix.as_str())
+	}
+}
+
+// returns the most specific rule that matches the sensor id. of several equally
+// specific rules, the first one wins
+pub fn find_rule<'a>(
+	rules: &'a [QuotaRule],
+	logfile_id: &LogfileID,
+) -> Option<(usize, &'a QuotaRule)> {
+	let mut found: Option<(usize, &QuotaRule)> = None;
+	for (idx, rule) in rules.iter().enumerate() {
+		if !rule.pattern.matches(logfile_id) {
+			continue;
+		}
+
+		let is_more_specific = match found {
+			Some((_, f)) => rule.pattern.get_specificity() > f.pattern.get_specificity(),
+			None => true,
+		};
+
+		if is_more_specific {
+			found = Some((idx, rule));
+		}
+	}
+
+	found
+}
+
+fn glob_matches(glob: &[char], id: &[char]) -> bool {
+	let mut g = 0;
+	let mut i = 0;
+	// the position of the last `*` and the position in the id it was matched at,
+	// so that the `*` can be extended by one character on a mismatch
+	let mut backtrack: Option<(usize, usize)> = None;
+
+	while i < id.len() {
+		// a `*` in the glob is always a wildcard, even if the id contains a `*`
+		if g < glob.len() && glob[g] == '*' {
+			backtrack = Some((g, i));
+			g += 1;
+		} else if g < glob.len() && (glob[g] == '?' || glob[g] == id[i]) {
+			g += 1;
+			i += 1;
+		} else if let Some((star_g, star_i)) = backtrack {
+			backtrack = Some((star_g, star_i + 1));
+			g = star_g + 1;
+			i = star_i + 1;
+		} else {
+			return false;
+		}
+	}
+
+	glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use logfile_config::LogfileConfig;
+
+	fn id(id: &str) -> LogfileID {
+		LogfileID::from_string(id.to_string())
+	}
+
+	fn glob(glob: &str, sensor_id: &str) -> bool {
+		QuotaPattern::Glob(glob.to_string()).matches(&id(sensor_id))
+	}
+
+	fn rule(pattern: QuotaPattern, limit_bytes: u64) -> QuotaRule {
+		QuotaRule::new(pattern, StorageQuota::Limited { limit_bytes })
+	}
+
+	fn prefix(prefix: &str) -> QuotaPattern {
+		QuotaPattern::Prefix(prefix.to_string())
+	}
+
+	fn find_rule_index(rules: &[QuotaRule], sensor_id: &str) -> Option<usize> {
+		find_rule(rules, &id(sensor_id)).map(|(index, _)| index)
+	}
+
+	#[test]
+	fn glob_matches_empty_id() {
+		assert!(glob("", ""));
+		assert!(glob("*", ""));
+		assert!(glob("**", ""));
+		assert!(!glob("?", ""));
+		assert!(!glob("a*", ""));
+		assert!(!glob("", "a"));
+	}
+
+	#[test]
+	fn glob_matches_wildcards() {
+		assert!(glob("plant.*", "plant."));
+		assert!(glob("plant.*", "plant.m1.temp"));
+		assert!(!glob("plant.*", "plant"));
+		assert!(glob("*.temp", "plant.m1.temp"));
+		assert!(!glob("*.temp", "plant.m1.temp2"));
+		assert!(glob("plant.*.temp", "plant.m1.temp"));
+		assert!(glob("plant.*.temp", "plant.m1.x.temp"));
+		assert!(!glob("plant.*.temp", "plant.temp"));
+		assert!(glob("plant.m?.temp", "plant.m1.temp"));
+		assert!(!glob("plant.m?.temp", "plant.m12.temp"));
+		assert!(glob("*?", "a"));
+		assert!(!glob("?*?", "a"));
+	}
+
+	#[test]
+	fn glob_matches_consecutive_stars() {
+		assert!(glob("a**b", "ab"));
+		assert!(glob("a**b", "axxb"));
+		assert!(glob("***", "abc"));
+		assert!(glob("a*?*c", "abc"));
+		assert!(!glob("a*?*c", "ac"));
+	}
+
+	#[test]
+	fn glob_matches_backtracking() {
+		assert!(glob("*ab", "aab"));
+		assert!(glob("*a*b", "xaxxaxb"));
+		assert!(!glob("*a*b", "xaxxax"));
+		assert!(glob("*x", "*ax"));
+		assert!(glob("a*", "a*"));
+	}
+
+	#[test]
+	fn specificity() {
+		assert_eq!(prefix("plant.").get_specificity(), 6);
+		assert_eq!(QuotaPattern::Glob("p*.?".to_string()).get_specificity(), 2);
+		assert_eq!(QuotaPattern::Glob("**".to_string()).get_specificity(), 0);
+	}
+
+	#[test]
+	fn find_rule_prefers_more_literal_characters() {
+		let rules = vec![
+			rule(prefix("plant."), 1),
+			rule(QuotaPattern::Glob("plant.*.temp".to_string()), 2),
+			rule(prefix("plant.m1."), 3),
+			rule(QuotaPattern::Glob("*".to_string()), 4),
+		];
+
+		// "plant.*.temp" has 11 literal characters, "plant.m1." has 9
+		assert_eq!(find_rule_index(&rules, "plant.m1.temp"), Some(1));
+		assert_eq!(find_rule_index(&rules, "plant.m1.pressure"), Some(2));
+		assert_eq!(find_rule_index(&rules, "plant.m2.pressure"), Some(0));
+		assert_eq!(find_rule_index(&rules, "other"), Some(3));
+		assert_eq!(find_rule_index(&rules[..3], "other"), None);
+	}
+
+	#[test]
+	fn find_rule_keeps_the_first_of_equally_specific_rules() {
+		let rules = vec![
+			rule(QuotaPattern::Glob("ab*".to_string()), 1),
+			rule(prefix("ab"), 2),
+			rule(QuotaPattern::Glob("a?b".to_string()), 3),
+		];
+
+		assert_eq!(find_rule_index(&rules, "abc"), Some(0));
+		assert_eq!(find_rule_index(&rules[1..], "abc"), Some(0));
+		assert_eq!(find_rule_index(&rules, "axb"), Some(2));
+	}
+
+	#[test]
+	fn explain_prefers_sensor_quota_over_rules() {
+		let mut config = LogfileConfig::new();
+		config.set_default_storage_quota(StorageQuota::Unlimited);
+		config.add_storage_quota_rule(rule(prefix("plant."), 1));
+		config.set_storage_quota_for(&id("plant.m1"), StorageQuota::Limited { limit_bytes: 2 });
+
+		let explanation = config.explain_storage_quota_for(&id("plant.m1"));
+		assert!(matches!(explanation.source, QuotaSource::Sensor));
+		assert!(matches!(
+			explanation.quota,
+			StorageQuota::Limited { limit_bytes: 2 }
+		));
+
+		let explanation = config.explain_storage_quota_for(&id("plant.m2"));
+		assert!(matches!(
+			explanation.source,
+			QuotaSource::Rule { index: 0, .. }
+		));
+		assert!(matches!(
+			explanation.quota,
+			StorageQuota::Limited { limit_bytes: 1 }
+		));
+
+		let explanation = config.explain_storage_quota_for(&id("other"));
+		assert!(matches!(explanation.source, QuotaSource::Default));
+	}
+}
@@ -0,0 +1,46 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use logfile_map::LogfileMap;
+use periodic_task::PeriodicTask;
+use std::time::Duration;
+
+// The retention sweeper drops measurements that are older than the max age of
+// their sensor from a background thread at a fixed interval. Without the sweeper,
+// old measurements are only dropped when new measurements are stored for the
+// same sensor. Once the sweeper is dropped, the thread exits.
+#[derive(Debug)]
+pub struct RetentionSweeper {
+	// only held to stop the thread once the sweeper is dropped
+	#[allow(dead_code)]
+	task: PeriodicTask,
+}
+
+impl RetentionSweeper {
+	pub fn start(logfile_map: LogfileMap, interval: Duration) -> Result<RetentionSweeper, ::Error> {
+		let task = PeriodicTask::start("sensorlog-retention", interval, move || {
+			if let Err(e) = logfile_map.expire() {
+				error!("Error while expiring measurements: {}", e);
+			}
+		})?;
+
+		Ok(RetentionSweeper { task })
+	}
+}
@@ -0,0 +1,215 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// A file that was opened for reading. Files stay readable after they were
+// removed from the backend, like open files on a unix filesystem
+pub trait StorageFile: Read + Seek + Debug + Send {}
+
+impl<T: Read + Seek + Debug + Send> StorageFile for T {}
+
+// The storage backend performs all file operations of the logfiles, so that the
+// logfiles can be kept in memory or run against a backend that injects faults.
+// Paths are always absolute or relative to the data directory; directories must
+// be created before files are written to them
+pub trait StorageBackend: Debug + Send + Sync {
+	// opens a file for reading. returns None if the file does not exist
+	fn open(&self, path: &Path) -> Result<Option<Box<dyn StorageFile>>, ::Error>;
+
+	// writes the data at the given offset, creating the file if it does not exist.
+	// the data is not synced, call sync before relying on it
+	fn append(&self, path: &Path, offset: u64, data: &[u8]) -> Result<(), ::Error>;
+
+	// replaces the contents of the file, creating the file if it does not exist
+	fn write(&self, path: &Path, data: &[u8]) -> Result<(), ::Error>;
+
+	fn truncate(&self, path: &Path, len: u64) -> Result<(), ::Error>;
+
+	// syncs the data of a file
+	fn sync(&self, path: &Path) -> Result<(), ::Error>;
+
+	// syncs the directory entries of a directory, e.g. after files were created,
+	// renamed or deleted in it
+	fn sync_dir(&self, path: &Path) -> Result<(), ::Error>;
+
+	// atomically replaces the file at the target path. directories can be
+	// renamed as long as the target path does not exist
+	fn rename(&self, from: &Path, to: &Path) -> Result<(), ::Error>;
+
+	fn remove(&self, path: &Path) -> Result<(), ::Error>;
+
+	// creates a directory. the parent directory must exist
+	fn create_dir(&self, path: &Path) -> Result<(), ::Error>;
+
+	// removes an empty directory
+	fn remove_dir(&self, path: &Path) -> Result<(), ::Error>;
+
+	// returns the names of all files and directories in a directory
+	fn list(&self, path: &Path) -> Result<Vec<String>, ::Error>;
+
+	// returns the length of a file or None if the file does not exist
+	fn get_len(&self, path: &Path) -> Result<Option<u64>, ::Error>;
+
+	fn exists(&self, path: &Path) -> bool;
+
+	fn is_dir(&self, path: &Path) -> bool;
+
+	// volatile backends lose all data when the process exits. the data directory
+	// of a volatile backend is not locked
+	fn is_volatile(&self) -> bool {
+		false
+	}
+
+	// discards all data and directory entries that were not synced, like a power
+	// loss would. only backends that keep track of the synced state support this,
+	// see storage_memory
+	fn simulate_crash(&self) -> Result<(), ::Error> {
+		Err(err_server!("the storage backend can not simulate a crash"))
+	}
+
+	fn read_at(&self, path: &Path, offset: u64, data: &mut [u8]) -> Result<(), ::Error> {
+		let mut file = match self.open(path)? {
+			Some(f) => f,
+			None => return Err(err_server!("file not found: {:?}", path)),
+		};
+
+		file.seek(SeekFrom::Start(offset))?;
+		file.read_exact(data)?;
+		Ok(())
+	}
+
+	// reads the whole file. returns None if the file does not exist
+	fn read(&self, path: &Path) -> Result<Option<Vec<u8>>, ::Error> {
+		let mut file = match self.open(path)? {
+			Some(f) => f,
+			None => return Ok(None),
+		};
+
+		let mut data = Vec::new();
+		file.read_to_end(&mut data)?;
+		Ok(Some(data))
+	}
+}
+
+// Stores the logfiles in the local filesystem
+#[derive(Debug, Clone, Default)]
+pub struct FsStorageBackend;
+
+impl FsStorageBackend {
+	pub fn new() -> FsStorageBackend {
+		FsStorageBackend
+	}
+}
+
+impl StorageBackend for FsStorageBackend {
+	fn open(&self, path: &Path) -> Result<Option<Box<dyn StorageFile>>, ::Error> {
+		match fs::File::open(path) {
+			Ok(f) => Ok(Some(Box::new(f))),
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	fn append(&self, path: &Path, offset: u64, data: &[u8]) -> Result<(), ::Error> {
+		let mut file_opts = fs::OpenOptions::new();
+		file_opts.write(true);
+		file_opts.create(true);
+		file_opts.truncate(false);
+
+		// N.B. there doesnt appear to be a binding to pwrite in the rust standard lib
+		let mut file = file_opts.open(path)?;
+		file.seek(SeekFrom::Start(offset))?;
+		file.write_all(data)?;
+
+		Ok(())
+	}
+
+	fn write(&self, path: &Path, data: &[u8]) -> Result<(), ::Error> {
+		let mut file = fs::File::create(path)?;
+		file.write_all(data)?;
+		Ok(())
+	}
+
+	fn truncate(&self, path: &Path, len: u64) -> Result<(), ::Error> {
+		let file = fs::OpenOptions::new().write(true).open(path)?;
+		file.set_len(len)?;
+		Ok(())
+	}
+
+	fn sync(&self, path: &Path) -> Result<(), ::Error> {
+		let file = fs::OpenOptions::new().write(true).open(path)?;
+		file.sync_data()?;
+		Ok(())
+	}
+
+	fn sync_dir(&self, path: &Path) -> Result<(), ::Error> {
+		fs::File::open(path)?.sync_all()?;
+		Ok(())
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> Result<(), ::Error> {
+		fs::rename(from, to)?;
+		Ok(())
+	}
+
+	fn remove(&self, path: &Path) -> Result<(), ::Error> {
+		fs::remove_file(path)?;
+		Ok(())
+	}
+
+	fn create_dir(&self, path: &Path) -> Result<(), ::Error> {
+		fs::create_dir(path)?;
+		Ok(())
+	}
+
+	fn remove_dir(&self, path: &Path) -> Result<(), ::Error> {
+		fs::remove_dir(path)?;
+		Ok(())
+	}
+
+	fn list(&self, path: &Path) -> Result<Vec<String>, ::Error> {
+		let mut names = Vec::<String>::new();
+		for dirent in fs::read_dir(path)? {
+			names.push(dirent?.file_name().into_string()?);
+		}
+
+		Ok(names)
+	}
+
+	fn get_len(&self, path: &Path) -> Result<Option<u64>, ::Error> {
+		match fs::metadata(path) {
+			Ok(m) => Ok(Some(m.len())),
+			Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		path.exists()
+	}
+
+	fn is_dir(&self, path: &Path) -> bool {
+		path.is_dir()
+	}
+}
@@ -0,0 +1,241 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use storage_backend::{StorageBackend, StorageFile};
+
+// Wraps another storage backend and injects write faults, so that the error
+// handling and the crash recovery can be exercised. All operations that modify
+// the backend (append, write, truncate, rename, remove, create_dir and
+// remove_dir) and syncs count as writes, so that a fault can be injected between
+// a write and the sync that makes it durable. Reads are not counted.
+//
+// A torn write only stores the first bytes of the data before it fails, like a
+// write that was interrupted by a crash. If crash_on_fault is set, all writes
+// and syncs fail after a fault was injected. restart then rolls the inner
+// backend back to its synced state, like a power loss would, see
+// StorageBackend::simulate_crash.
+#[derive(Debug)]
+pub struct FaultInjectingStorageBackend {
+	inner: Arc<dyn StorageBackend>,
+	state: Mutex<FaultState>,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+	writes: u64,
+	fault: Option<(u64, Option<u64>)>,
+	crash_on_fault: bool,
+	crashed: bool,
+}
+
+impl FaultInjectingStorageBackend {
+	pub fn new(inner: Arc<dyn StorageBackend>) -> FaultInjectingStorageBackend {
+		FaultInjectingStorageBackend {
+			inner,
+			state: Mutex::new(FaultState::default()),
+		}
+	}
+
+	// the n-th write from now on fails without modifying the inner backend
+	pub fn fail_nth_write(&self, n: u64) {
+		let mut state = self.lock();
+		state.fault = Some((state.writes + n, None));
+	}
+
+	// the n-th write from now on only stores the first bytes of the data and fails
+	pub fn tear_nth_write(&self, n: u64, bytes: u64) {
+		let mut state = self.lock();
+		state.fault = Some((state.writes + n, Some(bytes)));
+	}
+
+	pub fn set_crash_on_fault(&self, crash_on_fault: bool) {
+		self.lock().crash_on_fault = crash_on_fault;
+	}
+
+	// simulates a restart: if the storage has crashed, all data that was not
+	// synced is discarded from the inner backend. the faults are reset
+	pub fn restart(&self) -> Result<(), ::Error> {
+		if self.is_crashed() {
+			self.inner.simulate_crash()?;
+		}
+
+		self.reset();
+		Ok(())
+	}
+
+	// clears the pending fault and the crashed state
+	pub fn reset(&self) {
+		let mut state = self.lock();
+		state.fault = None;
+		state.crashed = false;
+	}
+
+	pub fn get_write_count(&self) -> u64 {
+		self.lock().writes
+	}
+
+	pub fn is_crashed(&self) -> bool {
+		self.lock().crashed
+	}
+
+	pub fn get_inner(&self) -> Arc<dyn StorageBackend> {
+		self.inner.clone()
+	}
+
+	fn lock(&self) -> MutexGuard<'_, FaultState> {
+		match self.state.lock() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		}
+	}
+
+	fn check_crashed(&self, path: &Path) -> Result<(), ::Error> {
+		if self.lock().crashed {
+			return Err(err_server!(
+				"injected fault: storage has crashed; path={:?}",
+				path
+			));
+		}
+
+		Ok(())
+	}
+
+	// counts the write and returns the fault that is injected into it. the outer
+	// option is set if the write fails, the inner option is the number of bytes
+	// that are stored before it fails
+	fn inject(&self, path: &Path) -> Result<Option<Option<u64>>, ::Error> {
+		self.check_crashed(path)?;
+
+		let mut state = self.lock();
+		state.writes += 1;
+
+		let tear = match state.fault {
+			Some((write, tear)) if write == state.writes => tear,
+			_ => return Ok(None),
+		};
+
+		warn!(
+			"Injecting storage fault; write={}, path={:?}, torn_bytes={:?}",
+			state.writes, path, tear
+		);
+
+		state.fault = None;
+		state.crashed = state.crash_on_fault;
+		Ok(Some(tear))
+	}
+
+	fn inject_write(&self, path: &Path) -> Result<(), ::Error> {
+		match self.inject(path)? {
+			Some(_) => Err(err_server!("injected fault: write failed; path={:?}", path)),
+			None => Ok(()),
+		}
+	}
+}
+
+impl StorageBackend for FaultInjectingStorageBackend {
+	fn open(&self, path: &Path) -> Result<Option<Box<dyn StorageFile>>, ::Error> {
+		self.inner.open(path)
+	}
+
+	fn append(&self, path: &Path, offset: u64, data: &[u8]) -> Result<(), ::Error> {
+		match self.inject(path)? {
+			Some(Some(bytes)) => {
+				let bytes = (bytes as usize).min(data.len());
+				self.inner.append(path, offset, &data[..bytes])?;
+				Err(err_server!("injected fault: torn write; path={:?}", path))
+			}
+			Some(None) => Err(err_server!("injected fault: write failed; path={:?}", path)),
+			None => self.inner.append(path, offset, data),
+		}
+	}
+
+	fn write(&self, path: &Path, data: &[u8]) -> Result<(), ::Error> {
+		match self.inject(path)? {
+			Some(Some(bytes)) => {
+				let bytes = (bytes as usize).min(data.len());
+				self.inner.write(path, &data[..bytes])?;
+				Err(err_server!("injected fault: torn write; path={:?}", path))
+			}
+			Some(None) => Err(err_server!("injected fault: write failed; path={:?}", path)),
+			None => self.inner.write(path, data),
+		}
+	}
+
+	fn truncate(&self, path: &Path, len: u64) -> Result<(), ::Error> {
+		self.inject_write(path)?;
+		self.inner.truncate(path, len)
+	}
+
+	fn sync(&self, path: &Path) -> Result<(), ::Error> {
+		self.inject_write(path)?;
+		self.inner.sync(path)
+	}
+
+	fn sync_dir(&self, path: &Path) -> Result<(), ::Error> {
+		self.inject_write(path)?;
+		self.inner.sync_dir(path)
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> Result<(), ::Error> {
+		self.inject_write(to)?;
+		self.inner.rename(from, to)
+	}
+
+	fn remove(&self, path: &Path) -> Result<(), ::Error> {
+		self.inject_write(path)?;
+		self.inner.remove(path)
+	}
+
+	fn create_dir(&self, path: &Path) -> Result<(), ::Error> {
+		self.inject_write(path)?;
+		self.inner.create_dir(path)
+	}
+
+	fn remove_dir(&self, path: &Path) -> Result<(), ::Error> {
+		self.inject_write(path)?;
+		self.inner.remove_dir(path)
+	}
+
+	fn list(&self, path: &Path) -> Result<Vec<String>, ::Error> {
+		self.inner.list(path)
+	}
+
+	fn get_len(&self, path: &Path) -> Result<Option<u64>, ::Error> {
+		self.inner.get_len(path)
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		self.inner.exists(path)
+	}
+
+	fn is_dir(&self, path: &Path) -> bool {
+		self.inner.is_dir(path)
+	}
+
+	fn is_volatile(&self) -> bool {
+		self.inner.is_volatile()
+	}
+
+	fn simulate_crash(&self) -> Result<(), ::Error> {
+		self.inner.simulate_crash()
+	}
+}
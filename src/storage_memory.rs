@@ -0,0 +1,498 @@
+/**
+ * Copyright © 2018 nyantec GmbH <oss@nyantec.com>
+ * Authors:
+ *	 Paul Asmuth <asm@nyantec.com>
+ *
+ * Provided that these terms and disclaimer and all copyright notices
+ * are retained or reproduced in an accompanying document, permission
+ * is granted to deal in this work without restriction, including un‐
+ * limited rights to use, publicly perform, distribute, sell, modify,
+ * merge, give away, or sublicence.
+ *
+ * This work is provided “AS IS” and WITHOUT WARRANTY of any kind, to
+ * the utmost extent permitted by applicable law, neither express nor
+ * implied; without malicious intent or gross negligence. In no event
+ * may a licensor, author or contributor be held liable for indirect,
+ * direct, other damage, loss, or other issues arising in any way out
+ * of dealing in the work, even if advised of the possibility of such
+ * damage or existence of a defect, except proven that it results out
+ * of said person’s immediate fault when using the work as intended.
+ */
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use storage_backend::{StorageBackend, StorageFile};
+
+type MemoryFileData = Arc<RwLock<MemoryFileContents>>;
+
+const ROOT_NODE: u64 = 0;
+
+// Keeps all files in memory. Files and directories are stored as nodes that are
+// referenced by directory entries, so a removed file stays readable through
+// handles that were opened before, like on a unix filesystem.
+//
+// The backend keeps track of the data and directory entries that were synced.
+// simulate_crash rolls the backend back to the synced state, so that the crash
+// recovery and the order of syncs can be tested: file data is durable once the
+// file was synced and directory entries are durable once the directory was
+// synced, regardless of the order in which the changes were made.
+#[derive(Debug)]
+pub struct MemoryStorageBackend {
+	state: Mutex<MemoryStorageState>,
+}
+
+#[derive(Debug)]
+struct MemoryStorageState {
+	nodes: HashMap<u64, MemoryNode>,
+	next_node: u64,
+	// the number of directory entries that were removed since the unreferenced
+	// nodes were last dropped
+	garbage: u64,
+}
+
+#[derive(Debug)]
+enum MemoryNode {
+	File(MemoryFileData),
+	Dir(MemoryDir),
+}
+
+#[derive(Debug, Default)]
+struct MemoryDir {
+	entries: BTreeMap<String, u64>,
+	entries_synced: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryFileContents {
+	data: Vec<u8>,
+	data_synced: Vec<u8>,
+	// the smallest offset that was modified since the last sync
+	dirty_offset: Option<usize>,
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+	data: MemoryFileData,
+	position: u64,
+}
+
+impl Default for MemoryStorageBackend {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl MemoryStorageBackend {
+	pub fn new() -> MemoryStorageBackend {
+		let mut nodes = HashMap::new();
+		nodes.insert(ROOT_NODE, MemoryNode::Dir(MemoryDir::default()));
+
+		MemoryStorageBackend {
+			state: Mutex::new(MemoryStorageState {
+				nodes,
+				next_node: ROOT_NODE + 1,
+				garbage: 0,
+			}),
+		}
+	}
+
+	// returns the total number of bytes stored in all files
+	pub fn get_size_bytes(&self) -> u64 {
+		let state = self.lock();
+		state
+			.nodes
+			.values()
+			.map(|n| match n {
+				MemoryNode::File(f) => lock_file(f).data.len() as u64,
+				MemoryNode::Dir(_) => 0,
+			})
+			.sum()
+	}
+
+	fn lock(&self) -> MutexGuard<'_, MemoryStorageState> {
+		match self.state.lock() {
+			Ok(l) => l,
+			Err(_) => fatal!("lock is poisoned"),
+		}
+	}
+}
+
+impl MemoryStorageState {
+	fn get_dir(&self, node: u64) -> Option<&MemoryDir> {
+		match self.nodes.get(&node) {
+			Some(MemoryNode::Dir(d)) => Some(d),
+			_ => None,
+		}
+	}
+
+	fn get_dir_mut(&mut self, node: u64) -> Option<&mut MemoryDir> {
+		match self.nodes.get_mut(&node) {
+			Some(MemoryNode::Dir(d)) => Some(d),
+			_ => None,
+		}
+	}
+
+	// returns the node at the path
+	fn lookup(&self, path: &Path) -> Option<u64> {
+		let mut node = ROOT_NODE;
+		for name in get_path_names(path) {
+			node = *self.get_dir(node)?.entries.get(name)?;
+		}
+
+		Some(node)
+	}
+
+	// returns the directory that contains the path and the name of the path in it
+	fn lookup_parent<'a>(&self, path: &'a Path) -> Result<(u64, &'a str), ::Error> {
+		let mut names = get_path_names(path);
+		let name = match names.pop() {
+			Some(name) => name,
+			None => return Err(err_server!("invalid path: {:?}", path)),
+		};
+
+		let mut node = ROOT_NODE;
+		for parent_name in names {
+			node = match self.get_dir(node).and_then(|d| d.entries.get(parent_name)) {
+				Some(n) => *n,
+				None => return Err(err_server!("directory not found: {:?}", path.parent())),
+			};
+		}
+
+		match self.get_dir(node) {
+			Some(_) => Ok((node, name)),
+			None => Err(err_server!("directory not found: {:?}", path.parent())),
+		}
+	}
+
+	fn get_file(&self, path: &Path) -> Result<MemoryFileData, ::Error> {
+		match self.lookup(path).and_then(|n| self.nodes.get(&n)) {
+			Some(MemoryNode::File(f)) => Ok(f.clone()),
+			_ => Err(err_server!("file not found: {:?}", path)),
+		}
+	}
+
+	// returns the file at the path, creating an empty file if it does not exist
+	fn create_file(&mut self, path: &Path) -> Result<MemoryFileData, ::Error> {
+		if let Some(node) = self.lookup(path) {
+			return match self.nodes.get(&node) {
+				Some(MemoryNode::File(f)) => Ok(f.clone()),
+				_ => Err(err_server!("not a file: {:?}", path)),
+			};
+		}
+
+		let file = MemoryFileData::default();
+		self.insert_node(path, MemoryNode::File(file.clone()))?;
+		Ok(file)
+	}
+
+	fn insert_node(&mut self, path: &Path, node: MemoryNode) -> Result<(), ::Error> {
+		let (parent, name) = self.lookup_parent(path)?;
+		let node_id = self.next_node;
+
+		match self.get_dir_mut(parent) {
+			Some(dir) => dir.entries.insert(name.to_string(), node_id),
+			None => return Err(err_server!("directory not found: {:?}", path.parent())),
+		};
+
+		self.nodes.insert(node_id, node);
+		self.next_node += 1;
+		Ok(())
+	}
+
+	// removes the directory entry. the node is dropped once it is no longer
+	// referenced by any directory entry, including the synced entries
+	fn remove_entry(&mut self, parent: u64, name: &str) {
+		if let Some(dir) = self.get_dir_mut(parent) {
+			dir.entries.remove(name);
+		}
+
+		self.garbage += 1;
+		if self.garbage as usize > self.nodes.len().max(64) {
+			self.drop_unreferenced_nodes();
+		}
+	}
+
+	fn drop_unreferenced_nodes(&mut self) {
+		let mut referenced = HashSet::new();
+		referenced.insert(ROOT_NODE);
+
+		let mut pending = vec![ROOT_NODE];
+		while let Some(node) = pending.pop() {
+			if let Some(dir) = self.get_dir(node) {
+				for child in dir.entries.values().chain(dir.entries_synced.values()) {
+					if referenced.insert(*child) {
+						pending.push(*child);
+					}
+				}
+			}
+		}
+
+		self.nodes.retain(|node, _| referenced.contains(node));
+		self.garbage = 0;
+	}
+}
+
+impl MemoryFileContents {
+	fn mark_dirty(&mut self, offset: usize) {
+		self.dirty_offset = Some(self.dirty_offset.map_or(offset, |o| o.min(offset)));
+	}
+}
+
+impl StorageBackend for MemoryStorageBackend {
+	fn open(&self, path: &Path) -> Result<Option<Box<dyn StorageFile>>, ::Error> {
+		let state = self.lock();
+		match state.lookup(path).and_then(|n| state.nodes.get(&n)) {
+			Some(MemoryNode::File(data)) => Ok(Some(Box::new(MemoryFile {
+				data: data.clone(),
+				position: 0,
+			}))),
+			_ => Ok(None),
+		}
+	}
+
+	fn append(&self, path: &Path, offset: u64, data: &[u8]) -> Result<(), ::Error> {
+		let file = self.lock().create_file(path)?;
+		let mut file = lock_file_mut(&file);
+
+		let offset = offset as usize;
+		if file.data.len() < offset + data.len() {
+			file.data.resize(offset + data.len(), 0);
+		}
+
+		file.data[offset..offset + data.len()].copy_from_slice(data);
+		file.mark_dirty(offset);
+		Ok(())
+	}
+
+	fn write(&self, path: &Path, data: &[u8]) -> Result<(), ::Error> {
+		let file = self.lock().create_file(path)?;
+		let mut file = lock_file_mut(&file);
+
+		file.data = data.to_vec();
+		file.mark_dirty(0);
+		Ok(())
+	}
+
+	fn truncate(&self, path: &Path, len: u64) -> Result<(), ::Error> {
+		let file = self.lock().get_file(path)?;
+		let mut file = lock_file_mut(&file);
+
+		let len = len as usize;
+		let len_prev = file.data.len();
+		file.data.resize(len, 0);
+		file.mark_dirty(len.min(len_prev));
+		Ok(())
+	}
+
+	fn sync(&self, path: &Path) -> Result<(), ::Error> {
+		let file = self.lock().get_file(path)?;
+		let mut file = lock_file_mut(&file);
+
+		// only copy the data that was modified since the last sync
+		if let Some(offset) = file.dirty_offset.take() {
+			let file = &mut *file;
+			let offset = offset.min(file.data.len());
+			file.data_synced.truncate(offset);
+			file.data_synced.extend_from_slice(&file.data[offset..]);
+		}
+
+		Ok(())
+	}
+
+	fn sync_dir(&self, path: &Path) -> Result<(), ::Error> {
+		let mut state = self.lock();
+		let node = match state.lookup(path) {
+			Some(n) => n,
+			None => return Err(err_server!("directory not found: {:?}", path)),
+		};
+
+		match state.get_dir_mut(node) {
+			Some(dir) => dir.entries_synced = dir.entries.clone(),
+			None => return Err(err_server!("not a directory: {:?}", path)),
+		};
+
+		Ok(())
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> Result<(), ::Error> {
+		let mut state = self.lock();
+		let (from_parent, from_name) = state.lookup_parent(from)?;
+		let (to_parent, to_name) = state.lookup_parent(to)?;
+
+		let node = match state.lookup(from) {
+			Some(n) => n,
+			None => return Err(err_server!("file not found: {:?}", from)),
+		};
+
+		let is_dir = state.get_dir(node).is_some();
+		if let Some(target) = state.lookup(to) {
+			if is_dir || state.get_dir(target).is_some() {
+				return Err(err_server!("file already exists: {:?}", to));
+			}
+		}
+
+		if from_parent == to_parent && from_name == to_name {
+			return Ok(());
+		}
+
+		// the new entry is inserted first, so that the node is never unreferenced
+		match state.get_dir_mut(to_parent) {
+			Some(dir) => dir.entries.insert(to_name.to_string(), node),
+			None => return Err(err_server!("directory not found: {:?}", to.parent())),
+		};
+
+		state.remove_entry(from_parent, from_name);
+		Ok(())
+	}
+
+	fn remove(&self, path: &Path) -> Result<(), ::Error> {
+		let mut state = self.lock();
+		let (parent, name) = state.lookup_parent(path)?;
+
+		match state.lookup(path) {
+			Some(n) if state.get_dir(n).is_none() => {}
+			_ => return Err(err_server!("file not found: {:?}", path)),
+		};
+
+		state.remove_entry(parent, name);
+		Ok(())
+	}
+
+	fn remove_dir(&self, path: &Path) -> Result<(), ::Error> {
+		let mut state = self.lock();
+		let (parent, name) = state.lookup_parent(path)?;
+
+		let is_empty = match state.lookup(path).and_then(|n| state.get_dir(n)) {
+			Some(dir) => dir.entries.is_empty(),
+			None => return Err(err_server!("directory not found: {:?}", path)),
+		};
+
+		if !is_empty {
+			return Err(err_server!("directory not empty: {:?}", path));
+		}
+
+		state.remove_entry(parent, name);
+		Ok(())
+	}
+
+	fn create_dir(&self, path: &Path) -> Result<(), ::Error> {
+		let mut state = self.lock();
+		if state.lookup(path).is_some() {
+			return Err(err_server!("file already exists: {:?}", path));
+		}
+
+		state.insert_node(path, MemoryNode::Dir(MemoryDir::default()))
+	}
+
+	fn list(&self, path: &Path) -> Result<Vec<String>, ::Error> {
+		let state = self.lock();
+		match state.lookup(path).and_then(|n| state.get_dir(n)) {
+			Some(dir) => Ok(dir.entries.keys().cloned().collect()),
+			None => Err(err_server!("directory not found: {:?}", path)),
+		}
+	}
+
+	fn get_len(&self, path: &Path) -> Result<Option<u64>, ::Error> {
+		let file = match self.lock().get_file(path) {
+			Ok(f) => f,
+			Err(_) => return Ok(None),
+		};
+
+		let len = lock_file(&file).data.len() as u64;
+		Ok(Some(len))
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		self.lock().lookup(path).is_some()
+	}
+
+	fn is_dir(&self, path: &Path) -> bool {
+		let state = self.lock();
+		state.lookup(path).and_then(|n| state.get_dir(n)).is_some()
+	}
+
+	fn is_volatile(&self) -> bool {
+		true
+	}
+
+	// rolls all files and directories back to the state of their last sync
+	fn simulate_crash(&self) -> Result<(), ::Error> {
+		let mut state = self.lock();
+		warn!("Simulating a crash; discarding all data that was not synced");
+
+		for node in state.nodes.values_mut() {
+			match node {
+				MemoryNode::File(f) => {
+					let mut file = lock_file_mut(f);
+					file.data = file.data_synced.clone();
+					file.dirty_offset = None;
+				}
+				MemoryNode::Dir(d) => d.entries = d.entries_synced.clone(),
+			}
+		}
+
+		state.drop_unreferenced_nodes();
+		Ok(())
+	}
+}
+
+impl Read for MemoryFile {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let file = lock_file(&self.data);
+
+		let position = (self.position as usize).min(file.data.len());
+		let len = buf.len().min(file.data.len() - position);
+		buf[..len].copy_from_slice(&file.data[position..position + len]);
+
+		self.position += len as u64;
+		Ok(len)
+	}
+}
+
+impl Seek for MemoryFile {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let len = lock_file(&self.data).data.len() as i64;
+
+		let position = match pos {
+			SeekFrom::Start(offset) => offset as i64,
+			SeekFrom::End(offset) => len + offset,
+			SeekFrom::Current(offset) => self.position as i64 + offset,
+		};
+
+		if position < 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"invalid seek to a negative position",
+			));
+		}
+
+		self.position = position as u64;
+		Ok(self.position)
+	}
+}
+
+// returns the names of the directories and the file in the path. paths are
+// resolved from the root directory, regardless of whether they are absolute
+fn get_path_names(path: &Path) -> Vec<&str> {
+	path.components()
+		.filter_map(|c| match c {
+			Component::Normal(name) => name.to_str(),
+			_ => None,
+		})
+		.collect()
+}
+
+fn lock_file(file: &MemoryFileData) -> ::std::sync::RwLockReadGuard<'_, MemoryFileContents> {
+	match file.read() {
+		Ok(l) => l,
+		Err(_) => fatal!("lock is poisoned"),
+	}
+}
+
+fn lock_file_mut(file: &MemoryFileData) -> ::std::sync::RwLockWriteGuard<'_, MemoryFileContents> {
+	match file.write() {
+		Ok(l) => l,
+		Err(_) => fatal!("lock is poisoned"),
+	}
+}
@@ -19,11 +19,76 @@
  * damage or existence of a defect, except proven that it results out
  * of said person’s immediate fault when using the work as intended.
  */
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+// A source for the current wall clock time in microseconds since the unix epoch.
+// All code that needs the current time should go through a Clock so that the
+// time can be controlled in tests and simulations.
+pub trait Clock: Debug + Send + Sync {
+	fn get_unix_microseconds(&self) -> Result<u64, ::Error>;
+}
+
+// The system wall clock
+#[derive(Debug, Clone, Default)]
+pub struct SystemClock;
+
+// A clock that only changes when it is explicitly set. Clones of a ManualClock
+// share the same time value.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+	time: Arc<AtomicU64>,
+}
+
 pub fn get_unix_microseconds() -> Result<u64, ::Error> {
 	match SystemTime::now().duration_since(UNIX_EPOCH) {
 		Ok(t) => Ok(t.as_secs() * 1000000 + t.subsec_nanos() as u64 / 1000),
 		Err(_) => Err(err_server!("cannot get current timestamp")),
 	}
 }
+
+impl Clock for SystemClock {
+	fn get_unix_microseconds(&self) -> Result<u64, ::Error> {
+		get_unix_microseconds()
+	}
+}
+
+impl ManualClock {
+	pub fn new(time: u64) -> ManualClock {
+		ManualClock {
+			time: Arc::new(AtomicU64::new(time)),
+		}
+	}
+
+	pub fn set(&self, time: u64) {
+		self.time.store(time, Ordering::SeqCst);
+	}
+
+	pub fn advance(&self, micros: u64) {
+		self.time.fetch_add(micros, Ordering::SeqCst);
+	}
+
+	// panics if the time would be rewound past zero
+	pub fn rewind(&self, micros: u64) {
+		let result = self
+			.time
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |time| {
+				time.checked_sub(micros)
+			});
+
+		if let Err(time) = result {
+			panic!(
+				"can not rewind the clock by {} microseconds from {}",
+				micros, time
+			);
+		}
+	}
+}
+
+impl Clock for ManualClock {
+	fn get_unix_microseconds(&self) -> Result<u64, ::Error> {
+		Ok(self.time.load(Ordering::SeqCst))
+	}
+}
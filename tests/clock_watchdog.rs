@@ -0,0 +1,154 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, new_backend, new_config, verify};
+use sensorlog::clock_watchdog::{ClockWatchdogConfig, ClockWatchdogMode};
+use sensorlog::error::ErrorCode;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::time::ManualClock;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+
+const MAX_JUMP_FORWARD: u64 = 3600 * 1_000_000;
+const MAX_JUMP_BACKWARD: u64 = 60 * 1_000_000;
+
+// far enough from zero to rewind the clock past the thresholds
+const TIME_START: u64 = 1_500_000_000 * 1_000_000;
+
+fn new_clock() -> Arc<ManualClock> {
+	Arc::new(ManualClock::new(TIME_START))
+}
+
+fn open(
+	backend: &Arc<dyn StorageBackend>,
+	clock: &Arc<ManualClock>,
+	mode: ClockWatchdogMode,
+) -> Sensorlog {
+	let mut config = new_config();
+	config.set_clock_watchdog(Some(ClockWatchdogConfig {
+		mode,
+		max_jump_forward: Some(MAX_JUMP_FORWARD),
+		max_jump_backward: Some(MAX_JUMP_BACKWARD),
+	}));
+
+	common::open_with_clock(backend.clone(), config, clock.clone())
+}
+
+fn store(service: &Sensorlog, sensor_id: &str) -> Result<(), sensorlog::error::Error> {
+	service.store_measurement(None, sensor_id, "x")
+}
+
+fn is_clock_error(result: Result<(), sensorlog::error::Error>) -> bool {
+	match result {
+		Err(e) => matches!(e.code, ErrorCode::ClockError),
+		Ok(()) => false,
+	}
+}
+
+#[test]
+fn jumps_within_the_thresholds_are_ignored() {
+	let backend = new_backend();
+	let clock = new_clock();
+	let service = open(&backend, &clock, ClockWatchdogMode::Panic);
+	store(&service, "sensor").unwrap();
+
+	clock.advance(MAX_JUMP_FORWARD);
+	store(&service, "sensor").unwrap();
+
+	clock.rewind(MAX_JUMP_BACKWARD);
+	store(&service, "sensor").unwrap();
+
+	// the observed time is not moved backwards, so two small backward jumps
+	// add up
+	clock.rewind(1);
+	assert!(is_clock_error(store(&service, "sensor")));
+}
+
+#[test]
+fn panic_on_forward_jump() {
+	let backend = new_backend();
+	let clock = new_clock();
+	let service = open(&backend, &clock, ClockWatchdogMode::Panic);
+	store(&service, "sensor").unwrap();
+
+	clock.advance(MAX_JUMP_FORWARD + 1);
+	assert!(is_clock_error(store(&service, "sensor")));
+
+	// the watchdog stays triggered, even once the clock is correct again
+	clock.rewind(MAX_JUMP_FORWARD + 1);
+	assert!(is_clock_error(store(&service, "sensor")));
+	assert!(service
+		.fetch_measurements("sensor", &Default::default())
+		.is_err());
+}
+
+#[test]
+fn panic_on_backward_jump() {
+	let backend = new_backend();
+	let clock = new_clock();
+	let service = open(&backend, &clock, ClockWatchdogMode::Panic);
+	store(&service, "sensor").unwrap();
+
+	clock.rewind(MAX_JUMP_BACKWARD + 1);
+	assert!(is_clock_error(store(&service, "sensor")));
+	drop(service);
+
+	// the measurements are kept
+	let service = common::open(backend.clone());
+	assert_eq!(fetch_times(&service, "sensor"), vec![TIME_START]);
+}
+
+#[test]
+fn wipe_on_jump() {
+	let backend = new_backend();
+	let clock = new_clock();
+	let service = open(&backend, &clock, ClockWatchdogMode::Wipe);
+	store(&service, "a").unwrap();
+	store(&service, "b").unwrap();
+
+	// the measurement that triggered the watchdog is stored after the wipe
+	clock.advance(MAX_JUMP_FORWARD + 1);
+	let time = TIME_START + MAX_JUMP_FORWARD + 1;
+	store(&service, "a").unwrap();
+	assert_eq!(fetch_times(&service, "a"), vec![time]);
+	assert!(fetch_times(&service, "b").is_empty());
+
+	clock.advance(1);
+	store(&service, "b").unwrap();
+	assert_eq!(fetch_times(&service, "b"), vec![time + 1]);
+	drop(service);
+
+	verify(backend.clone());
+
+	let service = open(&backend, &clock, ClockWatchdogMode::Wipe);
+	assert_eq!(fetch_times(&service, "a"), vec![time]);
+	assert_eq!(fetch_times(&service, "b"), vec![time + 1]);
+}
+
+#[test]
+fn check_after_restart() {
+	let backend = new_backend();
+	let clock = new_clock();
+	let service = open(&backend, &clock, ClockWatchdogMode::Panic);
+	store(&service, "sensor").unwrap();
+	drop(service);
+
+	// a downtime within the forward threshold
+	clock.advance(MAX_JUMP_FORWARD);
+	let service = open(&backend, &clock, ClockWatchdogMode::Panic);
+	store(&service, "sensor").unwrap();
+	drop(service);
+
+	// the clock was set back while sensorlog was not running
+	clock.rewind(MAX_JUMP_BACKWARD + 1);
+	let service = open(&backend, &clock, ClockWatchdogMode::Panic);
+	assert!(is_clock_error(store(&service, "sensor")));
+	drop(service);
+
+	// a downtime longer than the forward threshold can not be told apart from
+	// a forward jump
+	clock.advance(MAX_JUMP_BACKWARD + 1 + MAX_JUMP_FORWARD + 1);
+	let service = open(&backend, &clock, ClockWatchdogMode::Panic);
+	assert!(is_clock_error(store(&service, "sensor")));
+}
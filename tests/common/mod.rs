@@ -0,0 +1,126 @@
+// fixtures shared by the integration tests. not every test uses every fixture
+#![allow(dead_code)]
+
+use sensorlog::fetch::{FetchDirection, FetchOptions};
+use sensorlog::logfile_config::LogfileConfig;
+use sensorlog::logfile_directory::LogfileDirectory;
+use sensorlog::logfile_fsck;
+use sensorlog::logfile_id::LogfileID;
+use sensorlog::quota::StorageQuota;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::storage_fault::FaultInjectingStorageBackend;
+use sensorlog::storage_memory::MemoryStorageBackend;
+use sensorlog::time::{Clock, ManualClock};
+use sensorlog::Sensorlog;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub const DATADIR: &str = "/sensorlog";
+
+pub fn new_backend() -> Arc<dyn StorageBackend> {
+	Arc::new(MemoryStorageBackend::new())
+}
+
+pub fn new_fault_backend() -> Arc<FaultInjectingStorageBackend> {
+	Arc::new(FaultInjectingStorageBackend::new(Arc::new(
+		MemoryStorageBackend::new(),
+	)))
+}
+
+pub fn new_clock() -> Arc<ManualClock> {
+	Arc::new(ManualClock::new(1_000_000))
+}
+
+// unlimited storage and small partitions, so that a few measurements span
+// several partitions
+pub fn new_config() -> LogfileConfig {
+	let mut config = LogfileConfig::new();
+	config.set_default_storage_quota(StorageQuota::Unlimited);
+	config.set_default_partition_size_bytes(128);
+	config
+}
+
+pub fn open(backend: Arc<dyn StorageBackend>) -> Sensorlog {
+	open_with(backend, new_config())
+}
+
+pub fn open_with(backend: Arc<dyn StorageBackend>, config: LogfileConfig) -> Sensorlog {
+	open_with_clock(backend, config, new_clock())
+}
+
+pub fn open_with_clock(
+	backend: Arc<dyn StorageBackend>,
+	config: LogfileConfig,
+	clock: Arc<ManualClock>,
+) -> Sensorlog {
+	let clock: Arc<dyn Clock> = clock;
+	Sensorlog::with_backend(backend, Path::new(DATADIR), config, clock).unwrap()
+}
+
+pub fn get_logfile_path(sensor_id: &str) -> PathBuf {
+	let logfile_id = LogfileID::from_string(sensor_id.to_string());
+	Path::new(DATADIR)
+		.join("db")
+		.join(logfile_id.get_path().get_file_name())
+}
+
+pub fn fetch_times(service: &Sensorlog, sensor_id: &str) -> Vec<u64> {
+	let options = FetchOptions {
+		direction: FetchDirection::Ascending,
+		..Default::default()
+	};
+
+	service
+		.fetch_measurements(sensor_id, &options)
+		.unwrap()
+		.measurements
+		.into_iter()
+		.map(|m| m.time)
+		.collect()
+}
+
+pub fn store(service: &Sensorlog, sensor_id: &str, times: &[u64]) {
+	for time in times {
+		let data = format!("value-{}", time);
+		service
+			.store_measurement(Some(*time), sensor_id, data)
+			.unwrap();
+	}
+}
+
+// checks that fsck finds no errors in any logfile of the data directory
+pub fn verify(backend: Arc<dyn StorageBackend>) {
+	let directory = LogfileDirectory::open(backend, Path::new(DATADIR)).unwrap();
+	for report in logfile_fsck::verify_directory(&directory).unwrap() {
+		assert!(report.is_ok(), "{:?}", report.errors);
+	}
+}
+
+// a data directory on the file system for the tests that need the file system
+// backend. the directory is removed when it is dropped
+pub struct TempDir {
+	pub path: PathBuf,
+}
+
+impl TempDir {
+	pub fn new() -> TempDir {
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+		let path = ::std::env::temp_dir().join(format!(
+			"sensorlog-test-{}-{}",
+			::std::process::id(),
+			COUNTER.fetch_add(1, Ordering::SeqCst)
+		));
+
+		fs::create_dir_all(&path).unwrap();
+		TempDir { path }
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.path);
+	}
+}
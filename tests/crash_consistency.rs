@@ -0,0 +1,145 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, new_config, new_fault_backend, verify};
+use sensorlog::quota::StorageQuota;
+use sensorlog::storage_fault::FaultInjectingStorageBackend;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+
+// Crashes the storage at every write and sync of an interrupted store call, i.e.
+// between each of the commit steps listed in "Crash Consistency" in the README,
+// and checks the contract of the Always mode after the restart.
+
+const SENSORS: &[&str] = &["a", "b"];
+
+fn open(backend: &Arc<FaultInjectingStorageBackend>, quota: &StorageQuota) -> Sensorlog {
+	let mut config = new_config();
+	config.set_default_storage_quota(quota.clone());
+	common::open_with(backend.clone(), config)
+}
+
+fn store_batch(service: &Sensorlog, times: &[u64]) -> Result<(), sensorlog::error::Error> {
+	let mut batch = Vec::new();
+	for sensor_id in SENSORS {
+		for time in times {
+			batch.push((Some(*time), *sensor_id, format!("value-{}", time)));
+		}
+	}
+
+	let report = service.store_measurements(&batch)?;
+	match report.sensors_failed.into_iter().next() {
+		Some((_, e)) => Err(e),
+		None => Ok(()),
+	}
+}
+
+// runs the interrupted store with a crash at every write in turn, until the
+// store completes without hitting the fault
+fn run_with_crash<F>(quota: StorageQuota, inject: F, check_committed: fn(&[u64], &[u64]))
+where
+	F: Fn(&FaultInjectingStorageBackend, u64),
+{
+	let committed = (1..=20).collect::<Vec<u64>>();
+	let interrupted = (21..=25).collect::<Vec<u64>>();
+
+	let mut crash_points = 0;
+	for n in 1.. {
+		let backend = new_fault_backend();
+
+		let service = open(&backend, &quota);
+		for time in &committed {
+			store_batch(&service, &[*time]).unwrap();
+		}
+
+		backend.set_crash_on_fault(true);
+		inject(&backend, n);
+
+		let result = store_batch(&service, &interrupted);
+		let is_crashed = backend.is_crashed();
+		assert!(result.is_ok() || is_crashed);
+
+		drop(service);
+		backend.restart().unwrap();
+
+		let service = open(&backend, &quota);
+		for sensor_id in SENSORS {
+			let times = fetch_times(&service, sensor_id);
+
+			// the interrupted store is either fully visible or not at all, and
+			// fully visible if it returned successfully
+			let (times_committed, times_interrupted) =
+				times.split_at(times.iter().take_while(|t| **t <= 20).count());
+
+			assert!(
+				times_interrupted.is_empty() || times_interrupted == &interrupted[..],
+				"crash at write {}: sensor {:?} has {:?}",
+				n,
+				sensor_id,
+				times
+			);
+
+			if result.is_ok() {
+				assert_eq!(times_interrupted, &interrupted[..]);
+			}
+
+			check_committed(&committed, times_committed);
+		}
+
+		// the logfiles are consistent, and new measurements can be stored
+		store_batch(&service, &[26]).unwrap();
+		drop(service);
+
+		verify(backend.clone());
+
+		if !is_crashed {
+			break;
+		}
+
+		crash_points += 1;
+	}
+
+	// the store appends to both sensors, syncs the partitions, and writes and
+	// renames the transaction files
+	assert!(crash_points >= 8, "only {} crash points", crash_points);
+}
+
+// previously committed measurements are never lost
+fn check_all_committed(committed: &[u64], times: &[u64]) {
+	assert_eq!(times, committed);
+}
+
+// the garbage collection may drop the oldest committed measurements in the
+// interrupted commit, but never a measurement in between
+fn check_committed_suffix(committed: &[u64], times: &[u64]) {
+	assert!(!times.is_empty());
+	assert!(committed.ends_with(times), "{:?}", times);
+}
+
+#[test]
+fn crash_at_every_commit_step() {
+	run_with_crash(
+		StorageQuota::Unlimited,
+		|backend, n| backend.fail_nth_write(n),
+		check_all_committed,
+	);
+}
+
+#[test]
+fn torn_write_at_every_commit_step() {
+	run_with_crash(
+		StorageQuota::Unlimited,
+		|backend, n| backend.tear_nth_write(n, 9),
+		check_all_committed,
+	);
+}
+
+#[test]
+fn crash_at_every_commit_step_with_garbage_collection() {
+	run_with_crash(
+		StorageQuota::Limited { limit_bytes: 384 },
+		|backend, n| backend.fail_nth_write(n),
+		check_committed_suffix,
+	);
+}
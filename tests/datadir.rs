@@ -0,0 +1,88 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, new_clock, new_config, store, TempDir};
+use sensorlog::quota::StorageQuota;
+use sensorlog::time::Clock;
+use sensorlog::Sensorlog;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn open(datadir: &Path) -> Result<Sensorlog, sensorlog::error::Error> {
+	let clock: Arc<dyn Clock> = new_clock();
+	Sensorlog::new(datadir, new_config(), clock)
+}
+
+// returns the path, size and modification time of every file in the directory
+fn list_files(path: &Path) -> Vec<(PathBuf, u64, std::time::SystemTime)> {
+	let mut files = Vec::new();
+	for entry in fs::read_dir(path).unwrap() {
+		let entry = entry.unwrap();
+		let metadata = entry.metadata().unwrap();
+		if metadata.is_dir() {
+			files.extend(list_files(&entry.path()));
+		} else {
+			files.push((entry.path(), metadata.len(), metadata.modified().unwrap()));
+		}
+	}
+
+	files.sort();
+	files
+}
+
+#[test]
+fn second_open_fails_while_locked() {
+	let datadir = TempDir::new();
+	let service = open(&datadir.path).unwrap();
+
+	let error = open(&datadir.path).err().unwrap();
+	assert!(
+		error.message.contains(&format!(
+			"is locked by another process (pid {})",
+			std::process::id()
+		)),
+		"{}",
+		error.message
+	);
+
+	// the lock is released when the instance is dropped
+	drop(service);
+	open(&datadir.path).unwrap();
+}
+
+#[test]
+fn read_only_open_does_not_write() {
+	let datadir = TempDir::new();
+	let service = open(&datadir.path).unwrap();
+	store(&service, "sensor", &[1, 2, 3]);
+
+	// a read-only instance does not take the lock, so it can be opened while the
+	// writer is running
+	let reader = Sensorlog::open_read_only(&datadir.path).unwrap();
+	assert_eq!(fetch_times(&reader, "sensor"), vec![1, 2, 3]);
+
+	let files = list_files(&datadir.path);
+	assert!(reader.store_measurement(Some(4), "sensor", "x").is_err());
+	assert!(reader.store_measurement(Some(1), "other", "x").is_err());
+	assert!(reader
+		.store_measurements(&[(Some(4), "sensor", "x")])
+		.map_or(true, |report| !report.is_ok()));
+	assert!(reader.delete_range("sensor", 0, 10).is_err());
+	assert!(reader.delete_sensor("sensor").is_err());
+	assert!(reader
+		.set_storage_quota_for("sensor", StorageQuota::Unlimited)
+		.is_err());
+	assert_eq!(list_files(&datadir.path), files);
+	assert_eq!(fetch_times(&reader, "sensor"), vec![1, 2, 3]);
+
+	// measurements committed by the writer become visible on the next fetch
+	store(&service, "sensor", &[4]);
+	assert_eq!(fetch_times(&reader, "sensor"), vec![1, 2, 3, 4]);
+
+	// the read-only instance does not hold the lock once the writer is gone
+	drop(service);
+	let service = open(&datadir.path).unwrap();
+	assert_eq!(fetch_times(&service, "sensor"), vec![1, 2, 3, 4]);
+}
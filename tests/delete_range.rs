@@ -0,0 +1,104 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, get_logfile_path, new_backend, open, store, verify};
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+
+fn setup() -> (Arc<dyn StorageBackend>, Sensorlog) {
+	let backend = new_backend();
+	let service = open(backend.clone());
+	store(&service, "sensor", &(1..=30).collect::<Vec<_>>());
+	assert!(get_partitions(&service) >= 5);
+	(backend, service)
+}
+
+fn get_partitions(service: &Sensorlog) -> u64 {
+	service.get_storage_usage()[0].partitions
+}
+
+fn get_expected(from: u64, until: u64) -> Vec<u64> {
+	(1..=30).filter(|t| *t < from || *t >= until).collect()
+}
+
+// checks the measurements of the sensor, both before and after a restart
+fn check(backend: &Arc<dyn StorageBackend>, service: Sensorlog, expected: &[u64]) -> Sensorlog {
+	assert_eq!(fetch_times(&service, "sensor"), expected);
+	drop(service);
+
+	verify(backend.clone());
+
+	let service = open(backend.clone());
+	assert_eq!(fetch_times(&service, "sensor"), expected);
+	service
+}
+
+#[test]
+fn delete_range_within_partition() {
+	let (backend, service) = setup();
+	let partitions = get_partitions(&service);
+
+	service.delete_range("sensor", 2, 4).unwrap();
+	let service = check(&backend, service, &get_expected(2, 4));
+	assert_eq!(get_partitions(&service), partitions);
+}
+
+#[test]
+fn delete_range_across_partitions() {
+	let (backend, service) = setup();
+	let partitions = get_partitions(&service);
+
+	// the first and the last partition in the range are rewritten, the
+	// partitions in between are dropped
+	service.delete_range("sensor", 4, 18).unwrap();
+	let service = check(&backend, service, &get_expected(4, 18));
+	assert!(get_partitions(&service) < partitions);
+
+	service.store_measurement(Some(31), "sensor", "x").unwrap();
+	let mut expected = get_expected(4, 18);
+	expected.push(31);
+	check(&backend, service, &expected);
+}
+
+#[test]
+fn delete_range_including_head_partition() {
+	let (backend, service) = setup();
+
+	service.delete_range("sensor", 12, 1000).unwrap();
+	let service = check(&backend, service, &get_expected(12, 1000));
+
+	// the deleted measurements do not count as the newest stored measurement
+	service.store_measurement(Some(12), "sensor", "x").unwrap();
+	let mut expected = get_expected(12, 1000);
+	expected.push(12);
+	let service = check(&backend, service, &expected);
+
+	service.delete_range("sensor", 0, 1000).unwrap();
+	let service = check(&backend, service, &[]);
+
+	service.store_measurement(Some(1), "sensor", "x").unwrap();
+	check(&backend, service, &[1]);
+}
+
+#[test]
+fn delete_range_without_measurements() {
+	let (backend, service) = setup();
+	let path = get_logfile_path("sensor");
+	let mut files = backend.list(&path).unwrap();
+	files.sort();
+
+	// the partitions are left as they are. empty ranges are rejected
+	for &(from, until) in &[(100, 200), (0, 1), (10, 10), (20, 10)] {
+		let result = service.delete_range("sensor", from, until);
+		assert_eq!(result.is_ok(), from < until);
+
+		let mut files_after = backend.list(&path).unwrap();
+		files_after.sort();
+		assert_eq!(files_after, files);
+	}
+
+	let service = check(&backend, service, &get_expected(0, 0));
+	service.delete_range("unknown", 0, 1000).unwrap();
+}
@@ -0,0 +1,56 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{new_backend, new_config};
+use sensorlog::logfile_id::LogfileID;
+use sensorlog::quota::StorageQuota;
+use sensorlog::quota_rule::QuotaSource;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+
+fn open(backend: &Arc<dyn StorageBackend>) -> Sensorlog {
+	let mut config = new_config();
+	config.set_storage_quota_for(
+		&LogfileID::from_string("configured".to_string()),
+		StorageQuota::Limited { limit_bytes: 4096 },
+	);
+
+	common::open_with(backend.clone(), config)
+}
+
+#[test]
+fn delete_sensor_drops_quota_override() {
+	let backend = new_backend();
+	let service = open(&backend);
+
+	let quota = StorageQuota::Limited { limit_bytes: 1024 };
+	for sensor_id in &["configured", "default"] {
+		service.store_measurement(Some(1), sensor_id, "x").unwrap();
+		service
+			.set_storage_quota_for(sensor_id, quota.clone())
+			.unwrap();
+	}
+
+	service.delete_sensor("configured").unwrap();
+	service.delete_sensor("default").unwrap();
+
+	let explanation = service.explain_storage_quota_for("configured");
+	assert!(matches!(explanation.source, QuotaSource::Sensor));
+	assert!(matches!(
+		explanation.quota,
+		StorageQuota::Limited { limit_bytes: 4096 }
+	));
+
+	let explanation = service.explain_storage_quota_for("default");
+	assert!(matches!(explanation.source, QuotaSource::Default));
+
+	// the overrides are dropped from the data directory as well
+	drop(service);
+	let service = open(&backend);
+
+	let explanation = service.explain_storage_quota_for("default");
+	assert!(matches!(explanation.source, QuotaSource::Default));
+	assert!(matches!(explanation.quota, StorageQuota::Unlimited));
+}
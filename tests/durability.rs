@@ -0,0 +1,98 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, new_config, new_fault_backend};
+use sensorlog::durability::Durability;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::storage_fault::FaultInjectingStorageBackend;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn open(backend: &Arc<FaultInjectingStorageBackend>, durability: Durability) -> Sensorlog {
+	let mut config = new_config();
+	config.set_durability(durability);
+	common::open_with(backend.clone(), config)
+}
+
+fn store(service: &Sensorlog, times: ::std::ops::RangeInclusive<u64>) {
+	common::store(service, "sensor", &times.collect::<Vec<_>>());
+}
+
+// crashes the backend before the service is dropped, so that the final flush
+// does not reach the disk
+fn crash(backend: &FaultInjectingStorageBackend, service: Sensorlog) {
+	backend.set_crash_on_fault(true);
+	backend.fail_nth_write(1);
+	drop(service);
+	backend.restart().unwrap();
+}
+
+#[test]
+fn crash_loses_unflushed_commits_only() {
+	for &durability in &[
+		Durability::Interval(Duration::from_secs(3600)),
+		Durability::Never,
+	] {
+		let backend = new_fault_backend();
+
+		let service = open(&backend, durability);
+		store(&service, 1..=10);
+		service.flush().unwrap();
+		store(&service, 11..=20);
+		crash(&backend, service);
+
+		let service = open(&backend, durability);
+		assert_eq!(
+			fetch_times(&service, "sensor"),
+			(1..=10).collect::<Vec<_>>()
+		);
+
+		store(&service, 21..=30);
+		service.flush().unwrap();
+		crash(&backend, service);
+
+		let service = open(&backend, durability);
+		let expected = (1..=10).chain(21..=30).collect::<Vec<_>>();
+		assert_eq!(fetch_times(&service, "sensor"), expected);
+	}
+}
+
+#[test]
+fn shutdown_flushes_unsynced_commits() {
+	let backend = new_fault_backend();
+
+	let service = open(&backend, Durability::Never);
+	store(&service, 1..=10);
+	drop(service);
+	backend.simulate_crash().unwrap();
+
+	let service = open(&backend, Durability::Never);
+	assert_eq!(
+		fetch_times(&service, "sensor"),
+		(1..=10).collect::<Vec<_>>()
+	);
+}
+
+#[test]
+fn process_crash_keeps_unflushed_commits() {
+	for &durability in &[
+		Durability::Interval(Duration::from_secs(3600)),
+		Durability::Never,
+	] {
+		let backend = new_fault_backend();
+		let service = open(&backend, durability);
+		store(&service, 1..=20);
+
+		// the process dies without flushing, but the operating system keeps
+		// everything that was written
+		::std::mem::forget(service);
+
+		let service = open(&backend, durability);
+		assert_eq!(
+			fetch_times(&service, "sensor"),
+			(1..=20).collect::<Vec<_>>()
+		);
+	}
+}
@@ -0,0 +1,142 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{new_backend, open};
+use sensorlog::error::ErrorCode;
+use sensorlog::fetch::{FetchDirection, FetchOptions};
+use sensorlog::quota::StorageQuota;
+use sensorlog::Sensorlog;
+
+#[test]
+fn fetch_skips_measurements_larger_than_max_bytes() {
+	let service = open(new_backend());
+	for time in 1..=6 {
+		let size = if time == 1 || time == 4 { 100 } else { 3 };
+		service
+			.store_measurement(Some(time), "sensor", vec![b'x'; size])
+			.unwrap();
+	}
+
+	// each small measurement is encoded in 20 bytes, so a page holds two of them
+	let mut options = FetchOptions {
+		direction: FetchDirection::Ascending,
+		max_bytes: Some(40),
+		..Default::default()
+	};
+
+	let mut pages = Vec::new();
+	loop {
+		let result = service.fetch_measurements("sensor", &options).unwrap();
+		pages.push((
+			result
+				.measurements
+				.iter()
+				.map(|m| m.time)
+				.collect::<Vec<_>>(),
+			result.skipped,
+			result.truncated,
+		));
+
+		options.continuation = match result.continuation {
+			Some(continuation) => Some(continuation),
+			None => break,
+		};
+	}
+
+	// the measurement at time 4 is skipped while the first page is filled
+	assert_eq!(pages, vec![(vec![2, 3], 2, true), (vec![5, 6], 0, false)]);
+}
+
+// stores 40 measurements across several partitions. every time is used twice
+fn store_measurements(service: &Sensorlog) -> Vec<(u64, Vec<u8>)> {
+	let mut measurements = Vec::new();
+	for i in 0..40 {
+		let time = 1 + i / 2;
+		let data = format!("value-{}", i).into_bytes();
+		service
+			.store_measurement(Some(time), "sensor", &data)
+			.unwrap();
+		measurements.push((time, data));
+	}
+
+	assert!(service.get_storage_usage()[0].partitions >= 3);
+	measurements
+}
+
+#[test]
+fn cursor_pages_match_a_single_fetch() {
+	let service = open(new_backend());
+	store_measurements(&service);
+
+	for &direction in &[FetchDirection::Descending, FetchDirection::Ascending] {
+		let options = FetchOptions {
+			direction,
+			..Default::default()
+		};
+
+		let expected = service.fetch_measurements("sensor", &options).unwrap();
+		let expected = expected
+			.measurements
+			.into_iter()
+			.map(|m| (m.time, m.data))
+			.collect::<Vec<_>>();
+		assert_eq!(expected.len(), 40);
+
+		// read seven measurements at a time, opening a new cursor from the position
+		// of the previous one for every page
+		let mut pages = Vec::new();
+		let mut options = options.clone();
+		loop {
+			let mut cursor = service.fetch_cursor("sensor", &options).unwrap();
+			for measurement in cursor.by_ref().take(7) {
+				let measurement = measurement.unwrap();
+				pages.push((measurement.time, measurement.data));
+			}
+
+			options.continuation = match cursor.get_position() {
+				Some(position) => Some(position),
+				None => break,
+			};
+		}
+
+		assert_eq!(pages, expected);
+	}
+}
+
+#[test]
+fn continuation_into_collected_partition_fails() {
+	let service = open(new_backend());
+	let measurements = store_measurements(&service);
+
+	let options = FetchOptions {
+		direction: FetchDirection::Ascending,
+		limit: Some(1),
+		..Default::default()
+	};
+
+	let result = service.fetch_measurements("sensor", &options).unwrap();
+	assert_eq!(result.measurements[0].data, measurements[0].1);
+
+	// the oldest partitions are evicted right away when the quota is lowered
+	service
+		.set_storage_quota_for("sensor", StorageQuota::Limited { limit_bytes: 256 })
+		.unwrap();
+	assert!(service.get_storage_usage()[0].bytes <= 256);
+
+	let options = FetchOptions {
+		continuation: result.continuation,
+		..options
+	};
+
+	let error = service
+		.fetch_measurements("sensor", &options)
+		.err()
+		.unwrap();
+	assert!(matches!(error.code, ErrorCode::BadRequest));
+	assert!(
+		error.message.contains("garbage collected"),
+		"{}",
+		error.message
+	);
+}
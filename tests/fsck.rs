@@ -0,0 +1,112 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, get_logfile_path, new_backend, open, store, verify};
+use sensorlog::logfile_directory::LogfileDirectory;
+use sensorlog::logfile_fsck;
+use sensorlog::logfile_transaction::LogfileTransaction;
+use sensorlog::storage_backend::StorageBackend;
+use std::path::Path;
+use std::sync::Arc;
+
+fn repair(backend: &Arc<dyn StorageBackend>) -> Vec<logfile_fsck::FsckReport> {
+	let directory = LogfileDirectory::open(backend.clone(), Path::new("/sensorlog")).unwrap();
+	logfile_fsck::repair_directory(&directory, &[]).unwrap()
+}
+
+#[test]
+fn repair_ignores_zero_bytes() {
+	let backend = new_backend();
+	let service = open(backend.clone());
+	for time in 1..=3 {
+		service
+			.store_measurement(Some(time), "sensor", "x")
+			.unwrap();
+	}
+
+	drop(service);
+
+	// a crash after the partition file was extended but before the data was
+	// written, followed by the loss of the transaction file
+	let path = get_logfile_path("sensor");
+	let partition_path = path.join("p0.log");
+	let len = backend.get_len(&partition_path).unwrap().unwrap();
+	backend.append(&partition_path, len, &[0u8; 36]).unwrap();
+	backend.remove(&path.join("tx.lock")).unwrap();
+
+	let reports = repair(&backend);
+	assert_eq!(reports.len(), 1);
+	assert!(reports[0].repaired);
+	assert_eq!(reports[0].measurements, 3);
+	assert_eq!(reports[0].bytes_dropped, 36);
+
+	let service = open(backend.clone());
+	assert_eq!(fetch_times(&service, "sensor"), vec![1, 2, 3]);
+}
+
+#[test]
+fn repair_drops_legacy_records_in_sequenced_partitions() {
+	let backend = new_backend();
+	let service = open(backend.clone());
+	for time in 1..=3 {
+		service
+			.store_measurement(Some(time), "sensor", "x")
+			.unwrap();
+	}
+
+	drop(service);
+
+	// a record without a checksum, i.e. data | u32 data_size | u64 time
+	let mut record = b"x".to_vec();
+	record.extend_from_slice(&1u32.to_le_bytes());
+	record.extend_from_slice(&4u64.to_le_bytes());
+
+	let path = get_logfile_path("sensor");
+	let partition_path = path.join("p0.log");
+	let len = backend.get_len(&partition_path).unwrap().unwrap();
+	backend.append(&partition_path, len, &record).unwrap();
+	backend.remove(&path.join("tx.lock")).unwrap();
+
+	let reports = repair(&backend);
+	assert_eq!(reports[0].measurements, 3);
+	assert_eq!(reports[0].bytes_dropped, record.len() as u64);
+
+	let service = open(backend.clone());
+	assert_eq!(fetch_times(&service, "sensor"), vec![1, 2, 3]);
+}
+
+#[test]
+fn repair_moves_uncommitted_partitions_aside() {
+	let backend = new_backend();
+	let service = open(backend.clone());
+	let times = (1..=30).collect::<Vec<_>>();
+	store(&service, "sensor", &times);
+	drop(service);
+
+	// a partition file that is not in the transaction file, e.g. left behind by
+	// a crash, next to a head partition that lost its last record
+	let path = get_logfile_path("sensor");
+	let data = backend.read(&path.join("p0.log")).unwrap().unwrap();
+	backend.write(&path.join("p99.log"), &data).unwrap();
+
+	let transaction = LogfileTransaction::read_file(&*backend, &path.join("tx.lock")).unwrap();
+	let head = transaction.partitions.last().unwrap();
+	let head_path = path.join(format!("p{}.log", head.sequence.unwrap()));
+	backend.truncate(&head_path, head.offset - 1).unwrap();
+
+	let reports = repair(&backend);
+	assert_eq!(reports.len(), 1);
+	assert!(reports[0].repaired);
+	assert_eq!(reports[0].files_quarantined, 1);
+	assert_eq!(reports[0].measurements, 29);
+
+	assert!(!backend.exists(&path.join("p99.log")));
+	assert!(backend.exists(&path.join("p99.log.orphaned")));
+	verify(backend.clone());
+
+	// the partition is not picked up or deleted when the logfile is opened
+	let service = open(backend.clone());
+	assert_eq!(fetch_times(&service, "sensor"), &times[..29]);
+	assert!(backend.exists(&path.join("p99.log.orphaned")));
+}
@@ -0,0 +1,145 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, get_logfile_path, new_backend, new_config, store, verify};
+use sensorlog::error::ErrorCode;
+use sensorlog::fetch::{FetchDirection, FetchOptions};
+use sensorlog::logfile_config::OutOfOrderPolicy;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+
+fn open(backend: &Arc<dyn StorageBackend>, policy: OutOfOrderPolicy) -> Sensorlog {
+	let mut config = new_config();
+	config.set_default_out_of_order_policy(policy);
+	common::open_with(backend.clone(), config)
+}
+
+fn fetch_all(service: &Sensorlog) -> Vec<(u64, String)> {
+	let options = FetchOptions {
+		direction: FetchDirection::Ascending,
+		..Default::default()
+	};
+
+	service
+		.fetch_measurements("sensor", &options)
+		.unwrap()
+		.measurements
+		.into_iter()
+		.map(|m| (m.time, String::from_utf8(m.data).unwrap()))
+		.collect()
+}
+
+// returns the name and contents of every file in the logfile directory
+fn read_logfile(backend: &Arc<dyn StorageBackend>) -> Vec<(String, Option<Vec<u8>>)> {
+	let path = get_logfile_path("sensor");
+	let mut file_names = backend.list(&path).unwrap();
+	file_names.sort();
+
+	file_names
+		.into_iter()
+		.map(|n| {
+			let data = backend.read(&path.join(&n)).unwrap();
+			(n, data)
+		})
+		.collect()
+}
+
+#[test]
+fn reject_leaves_logfile_unchanged() {
+	let backend = new_backend();
+	let service = open(&backend, OutOfOrderPolicy::Reject);
+	store(&service, "sensor", &[10, 20, 30]);
+
+	let files = read_logfile(&backend);
+	let result = service.store_measurement(Some(15), "sensor", "late");
+	match result {
+		Err(e) => assert!(matches!(e.code, ErrorCode::BadRequest)),
+		Ok(()) => panic!("out of order measurement was stored"),
+	}
+
+	assert_eq!(read_logfile(&backend), files);
+	assert_eq!(fetch_times(&service, "sensor"), vec![10, 20, 30]);
+
+	// measurements with the same time as the newest one are in order
+	store(&service, "sensor", &[30, 31]);
+	drop(service);
+
+	let service = open(&backend, OutOfOrderPolicy::Reject);
+	assert_eq!(fetch_times(&service, "sensor"), vec![10, 20, 30, 30, 31]);
+}
+
+#[test]
+fn clamp_stores_with_newest_time() {
+	let backend = new_backend();
+	let service = open(&backend, OutOfOrderPolicy::Clamp);
+	store(&service, "sensor", &[10, 20, 30]);
+
+	service
+		.store_measurement(Some(15), "sensor", "late")
+		.unwrap();
+	store(&service, "sensor", &[31]);
+
+	let expected = vec![
+		(10, "value-10".to_string()),
+		(20, "value-20".to_string()),
+		(30, "value-30".to_string()),
+		(30, "late".to_string()),
+		(31, "value-31".to_string()),
+	];
+
+	assert_eq!(fetch_all(&service), expected);
+	drop(service);
+
+	verify(backend.clone());
+
+	let service = open(&backend, OutOfOrderPolicy::Clamp);
+	assert_eq!(fetch_all(&service), expected);
+}
+
+#[test]
+fn flush_drops_stored_measurements() {
+	let backend = new_backend();
+	let service = open(&backend, OutOfOrderPolicy::Flush);
+	store(&service, "sensor", &[10, 20, 30]);
+	store(&service, "sensor", &[15]);
+	assert_eq!(fetch_times(&service, "sensor"), vec![15]);
+	drop(service);
+
+	let service = open(&backend, OutOfOrderPolicy::Flush);
+	assert_eq!(fetch_times(&service, "sensor"), vec![15]);
+}
+
+#[test]
+fn regularly_late_measurements_are_merged() {
+	let backend = new_backend();
+	let service = open(&backend, OutOfOrderPolicy::LateBuffer);
+
+	// every third measurement arrives after the two measurements following it
+	let mut expected = Vec::new();
+	for i in 1..=300 {
+		let time = i * 10;
+		service
+			.store_measurement(Some(time), "sensor", "x")
+			.unwrap();
+		expected.push(time);
+
+		if i % 3 == 0 {
+			let time_late = time - 25;
+			service
+				.store_measurement(Some(time_late), "sensor", "late")
+				.unwrap();
+			expected.push(time_late);
+		}
+	}
+
+	expected.sort();
+	assert_eq!(fetch_times(&service, "sensor"), expected);
+	drop(service);
+
+	verify(backend.clone());
+
+	let service = open(&backend, OutOfOrderPolicy::LateBuffer);
+	assert_eq!(fetch_times(&service, "sensor"), expected);
+}
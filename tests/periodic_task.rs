@@ -0,0 +1,28 @@
+extern crate sensorlog;
+
+use sensorlog::periodic_task::PeriodicTask;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn periodic_task_runs_until_dropped() {
+	let runs = Arc::new(AtomicU64::new(0));
+	let runs_task = runs.clone();
+
+	let task = PeriodicTask::start("periodic-task-test", Duration::from_millis(1), move || {
+		runs_task.fetch_add(1, Ordering::SeqCst);
+	})
+	.unwrap();
+
+	while runs.load(Ordering::SeqCst) < 3 {
+		thread::sleep(Duration::from_millis(1));
+	}
+
+	// the thread is joined on drop, so no run happens afterwards
+	drop(task);
+	let runs_dropped = runs.load(Ordering::SeqCst);
+	thread::sleep(Duration::from_millis(10));
+	assert_eq!(runs.load(Ordering::SeqCst), runs_dropped);
+}
@@ -0,0 +1,157 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, get_logfile_path, new_backend};
+use sensorlog::logfile_config::LogfileConfig;
+use sensorlog::logfile_transaction::{LogfileTransaction, LogfileTransactionPartition};
+use sensorlog::measure::{Measurement, RecordFormat};
+use sensorlog::quota::StorageQuota;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::Sensorlog;
+use std::io::Cursor;
+use std::sync::Arc;
+
+fn encode_legacy(time: u64, data: &[u8]) -> Vec<u8> {
+	let mut encoded = data.to_vec();
+	encoded.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	encoded.extend_from_slice(&time.to_le_bytes());
+	encoded
+}
+
+fn encode(time: u64, data: &[u8]) -> Vec<u8> {
+	let measurement = Measurement {
+		time,
+		data: data.to_vec(),
+	};
+
+	let mut encoded = Cursor::new(Vec::new());
+	measurement.encode(&mut encoded, 0).unwrap();
+	encoded.into_inner()
+}
+
+fn decode(data: &[u8], format: RecordFormat) -> Result<(u64, Vec<u8>), sensorlog::error::Error> {
+	let mut file = Cursor::new(data.to_vec());
+	let (measurement, size) = Measurement::decode(&mut file, data.len() as u64, format)?;
+	assert_eq!(size, data.len() as u64);
+	Ok((measurement.time, measurement.data))
+}
+
+#[test]
+fn decode_record_formats() {
+	let record = encode(1234, b"value");
+	let expected = (1234, b"value".to_vec());
+	assert_eq!(decode(&record, RecordFormat::Current).unwrap(), expected);
+	assert_eq!(decode(&record, RecordFormat::Legacy).unwrap(), expected);
+
+	let record_legacy = encode_legacy(1234, b"value");
+	assert_eq!(
+		decode(&record_legacy, RecordFormat::Legacy).unwrap(),
+		expected
+	);
+	assert!(decode(&record_legacy, RecordFormat::Current).is_err());
+}
+
+#[test]
+fn decode_zero_bytes() {
+	for len in &[12, 17, 36] {
+		let zeros = vec![0u8; *len];
+		assert!(decode(&zeros, RecordFormat::Current).is_err());
+	}
+
+	// an empty legacy record with a time of zero
+	let zeros = vec![0u8; 12];
+	assert_eq!(
+		decode(&zeros, RecordFormat::Legacy).unwrap(),
+		(0, Vec::new())
+	);
+}
+
+// the default partition size keeps the migrated partition in a single file
+fn open(backend: &Arc<dyn StorageBackend>) -> Sensorlog {
+	let mut config = LogfileConfig::new();
+	config.set_default_storage_quota(StorageQuota::Unlimited);
+	common::open_with(backend.clone(), config)
+}
+
+// replaces the logfile of the sensor with a single legacy partition
+fn write_legacy_logfile(backend: &Arc<dyn StorageBackend>, times: &[u64]) {
+	let service = open(backend);
+	service.store_measurement(Some(1), "sensor", "x").unwrap();
+	drop(service);
+
+	let path = get_logfile_path("sensor");
+	backend.remove(&path.join("p0.log")).unwrap();
+
+	let mut partition = Vec::new();
+	for time in times {
+		partition.extend(encode_legacy(*time, &get_data(*time)));
+	}
+
+	let time_tail = times[0];
+	let time_head = times[times.len() - 1];
+	backend
+		.write(&path.join(format!("{}.log", time_tail)), &partition)
+		.unwrap();
+
+	let transaction = LogfileTransaction {
+		id: "sensor".to_string(),
+		partitions: vec![LogfileTransactionPartition {
+			sequence: None,
+			time_head,
+			time_tail,
+			offset: partition.len() as u64,
+		}],
+		late_partition: None,
+		next_sequence: 0,
+	};
+
+	transaction
+		.write_file(&**backend, &path.join("tx.lock"), true)
+		.unwrap();
+}
+
+// the payload of the measurement at the given time. the measurement at time 0
+// is empty, so that its legacy record consists of zero bytes only
+fn get_data(time: u64) -> Vec<u8> {
+	match time {
+		0 => Vec::new(),
+		time => format!("value-{}", time).into_bytes(),
+	}
+}
+
+#[test]
+fn migrate_legacy_partition() {
+	let backend = new_backend();
+	let times = (10..20).collect::<Vec<_>>();
+	write_legacy_logfile(&backend, &times);
+
+	// the partition is rewritten with checksums when it is migrated
+	let service = open(&backend);
+	let path = get_logfile_path("sensor");
+	assert!(!backend.exists(&path.join("10.log")));
+
+	let mut migrated = Vec::new();
+	for time in &times {
+		migrated.extend(encode(*time, &get_data(*time)));
+	}
+
+	assert_eq!(backend.read(&path.join("p0.log")).unwrap(), Some(migrated));
+	assert_eq!(fetch_times(&service, "sensor"), times);
+}
+
+#[test]
+fn migrate_legacy_partition_with_empty_record() {
+	let backend = new_backend();
+	let times = vec![0, 0, 10, 11];
+	write_legacy_logfile(&backend, &times);
+
+	let service = open(&backend);
+	assert_eq!(fetch_times(&service, "sensor"), times);
+
+	service.store_measurement(Some(12), "sensor", "x").unwrap();
+	drop(service);
+
+	let service = open(&backend);
+	assert_eq!(fetch_times(&service, "sensor"), vec![0, 0, 10, 11, 12]);
+}
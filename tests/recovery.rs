@@ -0,0 +1,90 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, get_logfile_path, new_backend, new_config, open, open_with, verify};
+use sensorlog::measure::{Measurement, RecordFormat};
+use sensorlog::storage_backend::StorageBackend;
+use std::path::Path;
+use std::sync::Arc;
+
+// drops the last byte of the partition file and returns the time of the record
+// that was cut off
+fn truncate_partition(backend: &Arc<dyn StorageBackend>, path: &Path) -> u64 {
+	let len = backend.get_len(path).unwrap().unwrap();
+	let mut file = backend.open(path).unwrap().unwrap();
+	let (measurement, _) = Measurement::decode(&mut file, len, RecordFormat::Current).unwrap();
+
+	backend.truncate(path, len - 1).unwrap();
+	measurement.time
+}
+
+#[test]
+fn recover_clamps_incomplete_partitions() {
+	let backend = new_backend();
+	let service = open(backend.clone());
+	common::store(&service, "sensor", &(1..=20).collect::<Vec<_>>());
+
+	drop(service);
+
+	// lose the tail of a partition in the middle and of the head partition
+	let path = get_logfile_path("sensor");
+	let mut partition_names = backend
+		.list(&path)
+		.unwrap()
+		.into_iter()
+		.filter(|n| n.ends_with(".log"))
+		.collect::<Vec<_>>();
+
+	partition_names.sort_by_key(|n| n[1..n.len() - 4].parse::<u64>().unwrap());
+	assert!(partition_names.len() >= 3);
+
+	let lost = [
+		truncate_partition(&backend, &path.join(&partition_names[1])),
+		truncate_partition(&backend, &path.join(partition_names.last().unwrap())),
+	];
+
+	let expected = (1..=20).filter(|t| !lost.contains(t)).collect::<Vec<_>>();
+
+	let service = open(backend.clone());
+	assert_eq!(fetch_times(&service, "sensor"), expected);
+
+	// the clamped partitions were committed and can be appended to
+	service.store_measurement(Some(21), "sensor", "x").unwrap();
+	drop(service);
+
+	verify(backend.clone());
+
+	let service = open(backend.clone());
+	let mut expected = expected;
+	expected.push(21);
+	assert_eq!(fetch_times(&service, "sensor"), expected);
+}
+
+#[test]
+fn recover_partition_damaged_at_the_beginning() {
+	let backend = new_backend();
+	let mut config = new_config();
+	config.set_default_partition_size_bytes(1 << 20);
+
+	let service = open_with(backend.clone(), config.clone());
+	common::store(&service, "sensor", &(1..=2000).collect::<Vec<_>>());
+	drop(service);
+
+	// no record can be decoded back to the beginning of the partition, so every
+	// record end is a candidate for the end of the intact records
+	let path = get_logfile_path("sensor").join("p0.log");
+	backend.append(&path, 0, b"?").unwrap();
+	truncate_partition(&backend, &path);
+
+	let service = open_with(backend.clone(), config.clone());
+	assert!(fetch_times(&service, "sensor").is_empty());
+
+	service.store_measurement(Some(1), "sensor", "x").unwrap();
+	drop(service);
+
+	verify(backend.clone());
+
+	let service = open_with(backend.clone(), config);
+	assert_eq!(fetch_times(&service, "sensor"), vec![1]);
+}
@@ -0,0 +1,137 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, new_backend, new_config, store, verify};
+use sensorlog::logfile_id::LogfileID;
+use sensorlog::quota::StorageQuota;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::time::ManualClock;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SECOND: u64 = 1_000_000;
+const TIME_START: u64 = 1_500_000_000 * SECOND;
+
+fn open(
+	backend: &Arc<dyn StorageBackend>,
+	clock: &Arc<ManualClock>,
+	sweep_interval: Duration,
+) -> Sensorlog {
+	let mut config = new_config();
+	config.set_default_max_age(Some(Duration::from_secs(100)));
+	config.set_max_age_for(
+		&LogfileID::from_string("short".to_string()),
+		Some(Duration::from_secs(10)),
+	);
+	config.set_max_age_for(&LogfileID::from_string("forever".to_string()), None);
+	config.set_storage_quota_for(
+		&LogfileID::from_string("quota".to_string()),
+		StorageQuota::Limited { limit_bytes: 256 },
+	);
+	config.set_retention_sweep_interval(sweep_interval);
+
+	common::open_with_clock(backend.clone(), config, clock.clone())
+}
+
+// one measurement every ten seconds for the last 200 seconds
+fn get_times() -> Vec<u64> {
+	(0..=20)
+		.map(|i| TIME_START - 200 * SECOND + i * 10 * SECOND)
+		.collect()
+}
+
+// checks that all measurements within the max age are kept and that older
+// measurements were dropped, except for those in the same partition as a
+// measurement within the max age
+fn check_max_age(service: &Sensorlog, sensor_id: &str, max_age: u64) {
+	let cutoff = TIME_START - max_age;
+	let times = fetch_times(service, sensor_id);
+	let times_expected = get_times()
+		.into_iter()
+		.filter(|t| *t >= cutoff)
+		.collect::<Vec<_>>();
+
+	assert!(times.ends_with(&times_expected), "{:?}", times);
+	assert!(times.len() < get_times().len(), "{:?}", times);
+}
+
+#[test]
+fn expire_by_max_age() {
+	let backend = new_backend();
+	let clock = Arc::new(ManualClock::new(TIME_START));
+	let service = open(&backend, &clock, Duration::from_secs(3600));
+
+	for sensor_id in &["default", "short", "forever"] {
+		store(&service, sensor_id, &get_times());
+	}
+
+	// expired partitions are dropped when new measurements are stored
+	check_max_age(&service, "default", 100 * SECOND);
+	check_max_age(&service, "short", 10 * SECOND);
+	assert_eq!(fetch_times(&service, "forever"), get_times());
+
+	// the head partition expires as well
+	clock.advance(1000 * SECOND);
+	service.expire_measurements().unwrap();
+	assert!(fetch_times(&service, "default").is_empty());
+	assert!(fetch_times(&service, "short").is_empty());
+	assert_eq!(fetch_times(&service, "forever"), get_times());
+
+	let time = TIME_START + 1000 * SECOND;
+	store(&service, "default", &[time]);
+	assert_eq!(fetch_times(&service, "default"), vec![time]);
+	drop(service);
+
+	verify(backend.clone());
+
+	let service = open(&backend, &clock, Duration::from_secs(3600));
+	assert_eq!(fetch_times(&service, "default"), vec![time]);
+	assert!(fetch_times(&service, "short").is_empty());
+	assert_eq!(fetch_times(&service, "forever"), get_times());
+}
+
+#[test]
+fn stricter_limit_wins() {
+	let backend = new_backend();
+	let clock = Arc::new(ManualClock::new(TIME_START));
+	let service = open(&backend, &clock, Duration::from_secs(3600));
+
+	// all measurements are within the max age, but exceed the quota
+	let times = (1..=30)
+		.map(|i| TIME_START - 50 * SECOND + i * SECOND)
+		.collect::<Vec<_>>();
+
+	store(&service, "quota", &times);
+	assert!(service.get_storage_usage()[0].bytes <= 256);
+
+	let times_kept = fetch_times(&service, "quota");
+	assert!(times_kept.len() < times.len());
+	assert!(times.ends_with(&times_kept));
+
+	// the quota is not used up, but the measurements are older than the max age
+	clock.advance(1000 * SECOND);
+	service.expire_measurements().unwrap();
+	assert!(fetch_times(&service, "quota").is_empty());
+}
+
+#[test]
+fn retention_sweeper_expires_in_background() {
+	let backend = new_backend();
+	let clock = Arc::new(ManualClock::new(TIME_START));
+	let service = open(&backend, &clock, Duration::from_millis(10));
+
+	store(&service, "default", &get_times());
+	store(&service, "forever", &get_times());
+	clock.advance(1000 * SECOND);
+
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while !fetch_times(&service, "default").is_empty() {
+		assert!(Instant::now() < deadline, "measurements were not expired");
+		thread::sleep(Duration::from_millis(10));
+	}
+
+	assert_eq!(fetch_times(&service, "forever"), get_times());
+}
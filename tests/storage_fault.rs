@@ -0,0 +1,144 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::new_fault_backend;
+use sensorlog::fetch::{FetchDirection, FetchOptions};
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::storage_fault::FaultInjectingStorageBackend;
+use sensorlog::storage_memory::MemoryStorageBackend;
+use sensorlog::Sensorlog;
+use std::path::Path;
+
+fn fetch_all(service: &Sensorlog, sensor_id: &str) -> Vec<(u64, Vec<u8>)> {
+	let options = FetchOptions {
+		direction: FetchDirection::Ascending,
+		..Default::default()
+	};
+
+	service
+		.fetch_measurements(sensor_id, &options)
+		.unwrap()
+		.measurements
+		.into_iter()
+		.map(|m| (m.time, m.data))
+		.collect()
+}
+
+fn measurement(time: u64) -> (u64, Vec<u8>) {
+	(time, format!("value-{}", time).into_bytes())
+}
+
+fn store(service: &Sensorlog, time: u64) -> Result<(), sensorlog::error::Error> {
+	service.store_measurement(Some(time), "sensor", measurement(time).1)
+}
+
+// injects a fault into every write of a store call in turn. the store either
+// fails or succeeds as a whole, and the logfile stays usable afterwards
+fn run_with_fault<F>(inject: F, crash: bool)
+where
+	F: Fn(&FaultInjectingStorageBackend, u64),
+{
+	for n in 1.. {
+		let backend = new_fault_backend();
+
+		let service = common::open(backend.clone());
+		for time in 1..=10 {
+			store(&service, time).unwrap();
+		}
+
+		backend.set_crash_on_fault(crash);
+		let write_count = backend.get_write_count();
+		inject(&backend, n);
+
+		let result = store(&service, 11);
+		let is_fault_injected = backend.get_write_count() - write_count >= n;
+		assert!(result.is_ok() || is_fault_injected);
+
+		// without a crash, the service stays readable after the failed store.
+		// the measurement may have been appended before a later write failed
+		if !crash {
+			backend.reset();
+
+			let mut expected = (1..=10).map(measurement).collect::<Vec<_>>();
+			let measurements = fetch_all(&service, "sensor");
+			if result.is_ok() || measurements.len() > expected.len() {
+				expected.push(measurement(11));
+			}
+
+			assert_eq!(measurements, expected, "fault injected into write {}", n);
+		}
+
+		drop(service);
+		backend.restart().unwrap();
+
+		let service = common::open(backend.clone());
+		let mut expected = (1..=10).map(measurement).collect::<Vec<_>>();
+		let measurements = fetch_all(&service, "sensor");
+		if result.is_ok() || measurements.len() > expected.len() {
+			expected.push(measurement(11));
+		}
+
+		assert_eq!(measurements, expected, "fault injected into write {}", n);
+
+		store(&service, 12).unwrap();
+		expected.push(measurement(12));
+		assert_eq!(fetch_all(&service, "sensor"), expected);
+
+		if !is_fault_injected {
+			break;
+		}
+	}
+}
+
+#[test]
+fn fail_nth_write() {
+	run_with_fault(|backend, n| backend.fail_nth_write(n), false);
+}
+
+#[test]
+fn fail_nth_write_with_crash() {
+	run_with_fault(|backend, n| backend.fail_nth_write(n), true);
+}
+
+#[test]
+fn tear_nth_write_with_crash() {
+	run_with_fault(|backend, n| backend.tear_nth_write(n, 7), true);
+}
+
+#[test]
+fn crash_discards_unsynced_data() {
+	let backend = MemoryStorageBackend::new();
+	let dir = Path::new("/dir");
+	let file = Path::new("/dir/file");
+
+	backend.create_dir(dir).unwrap();
+	backend.append(file, 0, b"synced").unwrap();
+	backend.sync(file).unwrap();
+	backend.sync_dir(dir).unwrap();
+	backend.append(file, 6, b" unsynced").unwrap();
+	backend
+		.append(Path::new("/dir/new"), 0, b"unsynced")
+		.unwrap();
+
+	// the directory itself was never synced to the root directory
+	backend.simulate_crash().unwrap();
+	assert!(!backend.exists(dir));
+
+	backend.create_dir(dir).unwrap();
+	backend.sync_dir(Path::new("/")).unwrap();
+	backend.append(file, 0, b"synced").unwrap();
+	backend.sync(file).unwrap();
+	backend.sync_dir(dir).unwrap();
+	backend.append(file, 6, b" unsynced").unwrap();
+	backend
+		.append(Path::new("/dir/new"), 0, b"unsynced")
+		.unwrap();
+	backend
+		.rename(Path::new("/dir/new"), Path::new("/dir/renamed"))
+		.unwrap();
+
+	backend.simulate_crash().unwrap();
+	assert_eq!(backend.read(file).unwrap(), Some(b"synced".to_vec()));
+	assert_eq!(backend.list(dir).unwrap(), vec!["file".to_string()]);
+}
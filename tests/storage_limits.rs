@@ -0,0 +1,113 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{new_backend, new_config, new_fault_backend};
+use sensorlog::durability::Durability;
+use sensorlog::quota::StorageQuota;
+use sensorlog::quota_rule::QuotaGroup;
+use sensorlog::storage_backend::StorageBackend;
+use sensorlog::Sensorlog;
+use std::sync::Arc;
+
+const BUDGET_BYTES: u64 = 1024;
+const GROUP_QUOTA_BYTES: u64 = 512;
+
+fn open(backend: Arc<dyn StorageBackend>, durability: Durability) -> Sensorlog {
+	let mut config = new_config();
+	config.set_durability(durability);
+	config.set_storage_budget(StorageQuota::Limited {
+		limit_bytes: BUDGET_BYTES,
+	});
+	config.add_quota_group(QuotaGroup::new(
+		"group.".to_string(),
+		StorageQuota::Limited {
+			limit_bytes: GROUP_QUOTA_BYTES,
+		},
+	));
+
+	common::open_with(backend, config)
+}
+
+fn get_total_bytes(service: &Sensorlog) -> u64 {
+	service
+		.get_storage_usage()
+		.iter()
+		.fold(0, |sum, usage| sum + usage.bytes)
+}
+
+fn get_group_bytes(service: &Sensorlog) -> u64 {
+	service.get_group_usage()[0].bytes
+}
+
+#[test]
+fn storage_limits_follow_running_totals() {
+	let service = open(new_backend(), Durability::Always);
+
+	for time in 1..=100 {
+		for sensor_id in &["group.a", "group.b", "other.a", "other.b"] {
+			service
+				.store_measurement(Some(time), sensor_id, "xxx")
+				.unwrap();
+		}
+
+		assert!(get_total_bytes(&service) <= BUDGET_BYTES);
+		assert!(get_group_bytes(&service) <= GROUP_QUOTA_BYTES);
+	}
+
+	// deleted measurements no longer count towards the limits, so nothing is
+	// evicted until the freed space is used up again
+	service.delete_sensor("other.a").unwrap();
+	service.delete_range("other.b", 0, 1000).unwrap();
+	let usage = service.get_storage_usage();
+
+	service
+		.store_measurement(Some(101), "other.c", "xxx")
+		.unwrap();
+	for usage in usage {
+		let usage_after = service
+			.get_storage_usage()
+			.into_iter()
+			.find(|u| u.id == usage.id)
+			.unwrap();
+
+		assert_eq!(usage_after.bytes, usage.bytes);
+	}
+}
+
+#[test]
+fn failed_eviction_does_not_fail_the_store() {
+	let backend = new_fault_backend();
+
+	// unsynced stores do not sync anything, so the first write after those of
+	// the store itself belongs to the synced commit of the eviction
+	let service = open(backend.clone(), Durability::Never);
+	let mut time = 0;
+	let mut store_write_count = 0;
+	while get_total_bytes(&service) + 20 <= BUDGET_BYTES {
+		time += 1;
+		let write_count = backend.get_write_count();
+		service
+			.store_measurement(Some(time), "other.a", "xxx")
+			.unwrap();
+		store_write_count = backend.get_write_count() - write_count;
+	}
+
+	let write_count = backend.get_write_count();
+	backend.fail_nth_write(store_write_count + 1);
+	service
+		.store_measurement(Some(time + 1), "other.a", "xxx")
+		.unwrap();
+	assert!(backend.get_write_count() - write_count > store_write_count);
+	assert!(get_total_bytes(&service) <= BUDGET_BYTES);
+
+	// the pending deletions are committed with the next store
+	backend.reset();
+	service
+		.store_measurement(Some(time + 2), "other.a", "xxx")
+		.unwrap();
+	drop(service);
+
+	let service = open(backend.clone(), Durability::Never);
+	assert!(get_total_bytes(&service) <= BUDGET_BYTES);
+}
@@ -0,0 +1,47 @@
+extern crate sensorlog;
+
+mod common;
+
+use common::{fetch_times, new_backend, new_config, store};
+use sensorlog::error::ErrorCode;
+use sensorlog::logfile_config::OutOfOrderPolicy;
+
+#[test]
+fn store_batch_attempts_every_sensor() {
+	let mut config = new_config();
+	config.set_default_out_of_order_policy(OutOfOrderPolicy::Reject);
+	let service = common::open_with(new_backend(), config);
+	store(&service, "b", &[20]);
+
+	// the second measurement for b is older than the newest stored one
+	let report = service
+		.store_measurements(&[
+			(Some(10), "a", "x"),
+			(Some(21), "b", "x"),
+			(Some(5), "b", "x"),
+			(Some(22), "b", "x"),
+			(Some(1), "c", "x"),
+		])
+		.unwrap();
+
+	assert!(!report.is_ok());
+	assert_eq!(report.sensors_stored, 2);
+	assert_eq!(report.sensors_failed.len(), 1);
+	assert_eq!(report.sensors_failed[0].0, "b");
+	assert!(matches!(
+		report.sensors_failed[0].1.code,
+		ErrorCode::BadRequest
+	));
+
+	// the measurements of b before the rejected one are committed
+	assert_eq!(fetch_times(&service, "a"), vec![10]);
+	assert_eq!(fetch_times(&service, "b"), vec![20, 21]);
+	assert_eq!(fetch_times(&service, "c"), vec![1]);
+
+	let report = service
+		.store_measurements(&[(Some(11), "a", "x"), (Some(23), "b", "x")])
+		.unwrap();
+
+	assert!(report.is_ok());
+	assert_eq!(report.sensors_stored, 2);
+}
@@ -0,0 +1,23 @@
+extern crate sensorlog;
+
+use sensorlog::time::{Clock, ManualClock};
+
+#[test]
+fn manual_clock() {
+	let clock = ManualClock::new(1000);
+	clock.advance(500);
+	clock.rewind(1500);
+	assert_eq!(clock.get_unix_microseconds().unwrap(), 0);
+
+	// clones share the same time
+	let clone = clock.clone();
+	clone.set(42);
+	assert_eq!(clock.get_unix_microseconds().unwrap(), 42);
+}
+
+#[test]
+#[should_panic(expected = "can not rewind the clock")]
+fn manual_clock_rewind_past_zero() {
+	let clock = ManualClock::new(1000);
+	clock.rewind(1001);
+}